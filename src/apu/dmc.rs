@@ -0,0 +1,194 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// https://www.nesdev.org/wiki/APU_DMC
+// Timer periods in CPU cycles for NTSC.
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+pub struct DMC {
+    irq_enabled: bool,
+    pub irq_flag: bool,
+    looping: bool,
+    timer: u16,
+    timer_period: u16,
+    output_level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl DMC {
+    pub fn new() -> Self {
+        DMC {
+            irq_enabled: false,
+            irq_flag: false,
+            looping: false,
+            timer: 0,
+            timer_period: DMC_RATE_TABLE[0],
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    /*
+     *   0x4010   IL-- RRRR   IRQ enable, loop, rate index
+     *   0x4011   -DDD DDDD   Direct load of the output level
+     *   0x4012   AAAA AAAA   Sample address (0xC000 + A * 64)
+     *   0x4013   LLLL LLLL   Sample length (L * 16 + 1 bytes)
+     */
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 0b10000000 != 0;
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+                self.looping = value & 0b1000000 != 0;
+                self.timer_period = DMC_RATE_TABLE[(value & 0b1111) as usize];
+            }
+            1 => self.output_level = value & 0b1111111,
+            2 => self.sample_address = 0xC000 | ((value as u16) << 6),
+            _ => self.sample_length = ((value as u16) << 4) | 1,
+        }
+    }
+
+    // Bit 4 of 0x4015 starts or stops sample playback.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // When the sample buffer has been emptied the memory reader wants the
+    // next byte, which the bus fetches on the DMC's behalf.
+    pub fn sample_address(&self) -> Option<u16> {
+        match (self.sample_buffer, self.bytes_remaining) {
+            (None, 1..) => Some(self.current_address),
+            _ => None,
+        }
+    }
+
+    pub fn load_sample(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // The address wraps around to 0x8000 rather than 0x0000.
+        self.current_address = match self.current_address {
+            0xFFFF => 0x8000,
+            address => address + 1,
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    // The table is in CPU cycles, so this is clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+        if !self.silence {
+            // Each bit moves the output level up or down by two, as long as
+            // it stays within 0 - 127.
+            if self.shift_register & 0b1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl Default for DMC {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for DMC {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_flag);
+        state.write_bool(self.looping);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+        state.write_u8(self.output_level);
+        state.write_u16(self.sample_address);
+        state.write_u16(self.sample_length);
+        state.write_u16(self.current_address);
+        state.write_u16(self.bytes_remaining);
+        state.write_bool(self.sample_buffer.is_some());
+        state.write_u8(self.sample_buffer.unwrap_or(0));
+        state.write_u8(self.shift_register);
+        state.write_u8(self.bits_remaining);
+        state.write_bool(self.silence);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enabled = state.read_bool()?;
+        self.irq_flag = state.read_bool()?;
+        self.looping = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        self.output_level = state.read_u8()?;
+        self.sample_address = state.read_u16()?;
+        self.sample_length = state.read_u16()?;
+        self.current_address = state.read_u16()?;
+        self.bytes_remaining = state.read_u16()?;
+        let buffered = state.read_bool()?;
+        let sample = state.read_u8()?;
+        self.sample_buffer = if buffered { Some(sample) } else { None };
+        self.shift_register = state.read_u8()?;
+        self.bits_remaining = state.read_u8()?;
+        self.silence = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// https://www.nesdev.org/wiki/APU_Envelope
+pub struct Envelope {
+    pub start: bool,
+    pub looping: bool,
+    pub constant_volume: bool,
+    pub volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            start: false,
+            looping: false,
+            constant_volume: false,
+            volume: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    // Bits 0-5 of the channel's first register: --LC VVVV
+    pub fn write(&mut self, value: u8) {
+        self.looping = value & 0b100000 != 0;
+        self.constant_volume = value & 0b10000 != 0;
+        self.volume = value & 0b1111;
+    }
+
+    // Clocked by quarter frames of the frame counter.
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.start);
+        state.write_bool(self.looping);
+        state.write_bool(self.constant_volume);
+        state.write_u8(self.volume);
+        state.write_u8(self.divider);
+        state.write_u8(self.decay);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.start = state.read_bool()?;
+        self.looping = state.read_bool()?;
+        self.constant_volume = state.read_bool()?;
+        self.volume = state.read_u8()?;
+        self.divider = state.read_u8()?;
+        self.decay = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use std::f32::consts::PI;
+
+// https://www.nesdev.org/wiki/APU_Mixer
+// The console's audio output path acts as a chain of first-order filters:
+// a high-pass at 90Hz, a high-pass at 440Hz and a low-pass at 14kHz.
+const OUTPUT_FILTERS: [(FilterKind, f32); 3] = [
+    (FilterKind::HighPass, 90.0),
+    (FilterKind::HighPass, 440.0),
+    (FilterKind::LowPass, 14000.0),
+];
+
+#[derive(Copy, Clone)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+pub struct Filter {
+    kind: FilterKind,
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = match kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+        Filter {
+            kind,
+            alpha,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => {
+                self.alpha * (self.previous_output + input - self.previous_input)
+            }
+            FilterKind::LowPass => {
+                self.previous_output + self.alpha * (input - self.previous_output)
+            }
+        };
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+pub struct OutputFilter {
+    filters: Vec<Filter>,
+}
+
+impl OutputFilter {
+    pub fn new(sample_rate: f32) -> Self {
+        OutputFilter {
+            filters: OUTPUT_FILTERS
+                .iter()
+                .map(|(kind, cutoff)| Filter::new(*kind, *cutoff, sample_rate))
+                .collect(),
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.filters
+            .iter_mut()
+            .fold(input, |sample, filter| filter.process(sample))
+    }
+}
@@ -0,0 +1,132 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// https://www.nesdev.org/wiki/APU_Frame_Counter
+// CPU cycles at which each step of the sequence happens.
+const FOUR_STEP_SEQUENCE: [usize; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP_SEQUENCE: [usize; 5] = [7457, 14913, 22371, 29829, 37281];
+const FOUR_STEP_LENGTH: usize = 29830;
+const FIVE_STEP_LENGTH: usize = 37282;
+
+// Units to clock on a given cycle. Half frames (length counters and sweeps)
+// always coincide with a quarter frame (envelopes and the linear counter).
+#[derive(PartialEq)]
+pub enum FrameClock {
+    None,
+    Quarter,
+    Half,
+}
+
+pub struct FrameCounter {
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    pub irq_flag: bool,
+    cycle: usize,
+    reset_delay: u8,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter {
+            five_step_mode: false,
+            irq_inhibit: false,
+            irq_flag: false,
+            cycle: 0,
+            reset_delay: 0,
+        }
+    }
+
+    /*
+     *   0x4017   MI-- ----   Sequencer mode (0: 4-step; 1: 5-step), IRQ inhibit
+     *
+     *   The sequencer is reset 3 or 4 CPU cycles after the write, depending on
+     *   whether it lands on an even or odd cycle.
+     */
+    pub fn write(&mut self, value: u8, odd_cycle: bool) {
+        self.five_step_mode = value & 0b10000000 != 0;
+        self.irq_inhibit = value & 0b1000000 != 0;
+        if self.irq_inhibit {
+            self.irq_flag = false;
+        }
+        self.reset_delay = match odd_cycle {
+            true => 4,
+            false => 3,
+        };
+    }
+
+    // Called every CPU cycle.
+    pub fn clock(&mut self) -> FrameClock {
+        if self.reset_delay > 0 {
+            self.reset_delay -= 1;
+            if self.reset_delay == 0 {
+                self.cycle = 0;
+                // Entering 5-step mode clocks every unit straight away.
+                if self.five_step_mode {
+                    return FrameClock::Half;
+                }
+                return FrameClock::None;
+            }
+        }
+
+        self.cycle += 1;
+        match self.five_step_mode {
+            false => self.clock_four_step(),
+            true => self.clock_five_step(),
+        }
+    }
+
+    fn clock_four_step(&mut self) -> FrameClock {
+        // The interrupt flag is raised over the last three cycles of the
+        // sequence, unless inhibited.
+        if self.cycle >= FOUR_STEP_LENGTH - 2 && !self.irq_inhibit {
+            self.irq_flag = true;
+        }
+        let clock = match FOUR_STEP_SEQUENCE.iter().position(|c| *c == self.cycle) {
+            Some(1) | Some(3) => FrameClock::Half,
+            Some(_) => FrameClock::Quarter,
+            None => FrameClock::None,
+        };
+        if self.cycle >= FOUR_STEP_LENGTH {
+            self.cycle = 0;
+        }
+        clock
+    }
+
+    fn clock_five_step(&mut self) -> FrameClock {
+        // Step four of the 5-step sequence does nothing.
+        let clock = match FIVE_STEP_SEQUENCE.iter().position(|c| *c == self.cycle) {
+            Some(1) | Some(4) => FrameClock::Half,
+            Some(3) => FrameClock::None,
+            Some(_) => FrameClock::Quarter,
+            None => FrameClock::None,
+        };
+        if self.cycle >= FIVE_STEP_LENGTH {
+            self.cycle = 0;
+        }
+        clock
+    }
+}
+
+impl Default for FrameCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for FrameCounter {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.five_step_mode);
+        state.write_bool(self.irq_inhibit);
+        state.write_bool(self.irq_flag);
+        state.write_usize(self.cycle);
+        state.write_u8(self.reset_delay);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.five_step_mode = state.read_bool()?;
+        self.irq_inhibit = state.read_bool()?;
+        self.irq_flag = state.read_bool()?;
+        self.cycle = state.read_usize()?;
+        self.reset_delay = state.read_u8()?;
+        Ok(())
+    }
+}
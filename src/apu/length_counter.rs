@@ -0,0 +1,69 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// https://www.nesdev.org/wiki/APU_Length_Counter
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub halt: bool,
+    pub counter: u8,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        LengthCounter {
+            enabled: false,
+            halt: false,
+            counter: 0,
+        }
+    }
+
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0b11111) as usize];
+        }
+    }
+
+    // Clearing the enable bit in 0x4015 immediately silences the channel.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    // Clocked by half frames of the frame counter.
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+impl Default for LengthCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.halt);
+        state.write_u8(self.counter);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.halt = state.read_bool()?;
+        self.counter = state.read_u8()?;
+        Ok(())
+    }
+}
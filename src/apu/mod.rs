@@ -0,0 +1,204 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use dmc::DMC;
+use frame_counter::FrameClock;
+use frame_counter::FrameCounter;
+use noise::Noise;
+use pulse::Pulse;
+use pulse::PulseChannel;
+use resampler::Resampler;
+use triangle::Triangle;
+
+pub mod dmc;
+pub mod envelope;
+pub mod filter;
+pub mod frame_counter;
+pub mod length_counter;
+pub mod noise;
+pub mod pulse;
+pub mod resampler;
+pub mod triangle;
+
+pub const SAMPLE_RATE: u32 = 44100;
+pub const CPU_CLOCK_RATE: u32 = 1789773;
+
+pub struct APU {
+    pub pulse_one: Pulse,
+    pub pulse_two: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: DMC,
+    pub frame_counter: FrameCounter,
+    cycles: usize,
+    resampler: Resampler,
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse_one: Pulse::new(PulseChannel::One),
+            pulse_two: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: DMC::new(),
+            frame_counter: FrameCounter::new(),
+            cycles: 0,
+            resampler: Resampler::new(CPU_CLOCK_RATE, SAMPLE_RATE),
+        }
+    }
+
+    // The host may not give us the sample rate we asked for, typically
+    // 48kHz instead of 44.1kHz. The clock rate differs from CPU_CLOCK_RATE
+    // when frames are paced at another region's refresh rate.
+    pub fn set_output(&mut self, clock_rate: u32, sample_rate: u32) {
+        self.resampler = Resampler::new(clock_rate, sample_rate);
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse_one.write_register(address - 0x4000, value),
+            0x4004..=0x4007 => self.pulse_two.write_register(address - 0x4004, value),
+            0x4008..=0x400B => self.triangle.write_register(address - 0x4008, value),
+            0x400C..=0x400F => self.noise.write_register(address - 0x400C, value),
+            0x4010..=0x4013 => self.dmc.write_register(address - 0x4010, value),
+            0x4015 => {
+                self.pulse_one.length_counter.set_enabled(value & 0b1 != 0);
+                self.pulse_two.length_counter.set_enabled(value & 0b10 != 0);
+                self.triangle.length_counter.set_enabled(value & 0b100 != 0);
+                self.noise.length_counter.set_enabled(value & 0b1000 != 0);
+                self.dmc.set_enabled(value & 0b10000 != 0);
+            }
+            0x4017 => self.frame_counter.write(value, self.cycles % 2 == 1),
+            _ => {}
+        }
+    }
+
+    // 0x4015 reports which channels still have a non-zero length counter,
+    // whether the DMC has bytes left to play and both interrupt flags.
+    // Reading clears the frame interrupt flag.
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.pulse_one.length_counter.is_active() {
+            status |= 0b1;
+        }
+        if self.pulse_two.length_counter.is_active() {
+            status |= 0b10;
+        }
+        if self.triangle.length_counter.is_active() {
+            status |= 0b100;
+        }
+        if self.noise.length_counter.is_active() {
+            status |= 0b1000;
+        }
+        if self.dmc.is_active() {
+            status |= 0b10000;
+        }
+        if self.frame_counter.irq_flag {
+            status |= 0b1000000;
+        }
+        if self.dmc.irq_flag {
+            status |= 0b10000000;
+        }
+        self.frame_counter.irq_flag = false;
+        status
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.frame_counter.irq_flag || self.dmc.irq_flag
+    }
+
+    pub fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.cycles += 1;
+            // Pulse timers run at half the CPU clock.
+            if self.cycles.is_multiple_of(2) {
+                self.pulse_one.clock_timer();
+                self.pulse_two.clock_timer();
+            }
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+            self.clock_frame_counter();
+            self.resampler.clock(self.mix());
+        }
+    }
+
+    fn clock_frame_counter(&mut self) {
+        match self.frame_counter.clock() {
+            FrameClock::Quarter => self.clock_quarter_frame(),
+            FrameClock::Half => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            FrameClock::None => {}
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_one.envelope.clock();
+        self.pulse_two.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear_counter();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse_one.length_counter.clock();
+        self.pulse_two.length_counter.clock();
+        self.triangle.length_counter.clock();
+        self.noise.length_counter.clock();
+        self.pulse_one.clock_sweep();
+        self.pulse_two.clock_sweep();
+    }
+
+    // https://www.nesdev.org/wiki/APU_Mixer
+    // The channels are combined non-linearly, in two groups which interact
+    // with one another through the resistor networks on the DAC outputs.
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse_one.output() + self.pulse_two.output()) as f32;
+        let pulse_out = match pulse == 0.0 {
+            true => 0.0,
+            false => 95.88 / (8128.0 / pulse + 100.0),
+        };
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = match tnd == 0.0 {
+            true => 0.0,
+            false => 159.79 / (1.0 / tnd + 100.0),
+        };
+        pulse_out + tnd_out
+    }
+
+    // Hand over the samples produced since the last call.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.resampler.take_samples()
+    }
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for APU {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.pulse_one.save_state(state);
+        self.pulse_two.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        self.frame_counter.save_state(state);
+        state.write_usize(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse_one.load_state(state)?;
+        self.pulse_two.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.frame_counter.load_state(state)?;
+        self.cycles = state.read_usize()?;
+        Ok(())
+    }
+}
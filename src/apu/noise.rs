@@ -0,0 +1,110 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// https://www.nesdev.org/wiki/APU_Noise
+// Timer periods in CPU cycles for NTSC.
+const NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+    short_mode: bool,
+    shift_register: u16,
+    timer: u16,
+    timer_period: u16,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            short_mode: false,
+            // The shift register is loaded with 1 on power-up.
+            shift_register: 1,
+            timer: 0,
+            timer_period: NOISE_PERIOD_TABLE[0],
+        }
+    }
+
+    /*
+     *   0x400C   --LC VVVV   Length counter halt / envelope loop, constant volume, volume
+     *   0x400E   M--- PPPP   Mode, period index
+     *   0x400F   LLLL L---   Length counter load
+     */
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length_counter.halt = value & 0b100000 != 0;
+                self.envelope.write(value);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = value & 0b10000000 != 0;
+                self.timer_period = NOISE_PERIOD_TABLE[(value & 0b1111) as usize];
+            }
+            _ => {
+                self.length_counter.load(value >> 3);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // The table is in CPU cycles, so this is clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            self.clock_shift_register();
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // 15-bit LFSR. Feedback is bit 0 XOR bit 1, or bit 0 XOR bit 6 in short
+    // mode which gives a 93-step metallic sequence.
+    fn clock_shift_register(&mut self) {
+        let tap = match self.short_mode {
+            true => 6,
+            false => 1,
+        };
+        let feedback = (self.shift_register & 0b1) ^ ((self.shift_register >> tap) & 0b1);
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 0b1 != 0 || !self.length_counter.is_active() {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for Noise {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.envelope.save_state(state);
+        self.length_counter.save_state(state);
+        state.write_bool(self.short_mode);
+        state.write_u16(self.shift_register);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.envelope.load_state(state)?;
+        self.length_counter.load_state(state)?;
+        self.short_mode = state.read_bool()?;
+        self.shift_register = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// https://www.nesdev.org/wiki/APU_Pulse
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// The two pulse channels differ only in how the sweep unit negates: pulse 1
+// adds the ones' complement of the change, so it sweeps one lower.
+#[derive(Copy, Clone, PartialEq)]
+pub enum PulseChannel {
+    One,
+    Two,
+}
+
+pub struct Pulse {
+    channel: PulseChannel,
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+    duty: u8,
+    sequence_step: u8,
+    timer: u16,
+    timer_period: u16,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Self {
+        Pulse {
+            channel,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            duty: 0,
+            sequence_step: 0,
+            timer: 0,
+            timer_period: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    /*
+     *   0x4000 / 0x4004   DDLC VVVV   Duty, length counter halt / envelope loop,
+     *                                 constant volume, volume / envelope period
+     *   0x4001 / 0x4005   EPPP NSSS   Sweep enabled, period, negate, shift
+     *   0x4002 / 0x4006   TTTT TTTT   Timer low
+     *   0x4003 / 0x4007   LLLL LTTT   Length counter load, timer high
+     */
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length_counter.halt = value & 0b100000 != 0;
+                self.envelope.write(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 0b10000000 != 0;
+                self.sweep_period = (value >> 4) & 0b111;
+                self.sweep_negate = value & 0b1000 != 0;
+                self.sweep_shift = value & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => {
+                self.timer_period = (self.timer_period & 0x0700) | value as u16;
+            }
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0b111) << 8);
+                self.length_counter.load(value >> 3);
+                // Restart the sequencer and envelope.
+                self.sequence_step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // Clocked every APU cycle (every second CPU cycle).
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        match (self.sweep_negate, self.channel) {
+            (false, _) => self.timer_period + change,
+            (true, PulseChannel::One) => self.timer_period.saturating_sub(change + 1),
+            (true, PulseChannel::Two) => self.timer_period.saturating_sub(change),
+        }
+    }
+
+    // The channel is silenced when the period is too low to be audible or
+    // the sweep would push it out of range, even while the sweep is disabled.
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target_period() > 0x7FF
+    }
+
+    // Clocked by half frames of the frame counter.
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted()
+        {
+            self.timer_period = self.sweep_target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0
+            || !self.length_counter.is_active()
+            || self.is_muted()
+        {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.envelope.save_state(state);
+        self.length_counter.save_state(state);
+        state.write_u8(self.duty);
+        state.write_u8(self.sequence_step);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+        state.write_bool(self.sweep_enabled);
+        state.write_u8(self.sweep_period);
+        state.write_bool(self.sweep_negate);
+        state.write_u8(self.sweep_shift);
+        state.write_u8(self.sweep_divider);
+        state.write_bool(self.sweep_reload);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.envelope.load_state(state)?;
+        self.length_counter.load_state(state)?;
+        self.duty = state.read_u8()?;
+        self.sequence_step = state.read_u8()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        self.sweep_enabled = state.read_bool()?;
+        self.sweep_period = state.read_u8()?;
+        self.sweep_negate = state.read_bool()?;
+        self.sweep_shift = state.read_u8()?;
+        self.sweep_divider = state.read_u8()?;
+        self.sweep_reload = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use super::filter::OutputFilter;
+
+// Each change in amplitude is drawn into the output as a band-limited step,
+// spread over KERNEL_WIDTH output samples. The kernel is precomputed at
+// KERNEL_PHASES sub-sample offsets.
+const KERNEL_WIDTH: usize = 16;
+const KERNEL_PHASES: usize = 64;
+// Cutoff of the kernel as a fraction of the output sample rate, kept a
+// little under Nyquist to leave room for the window's transition band.
+const KERNEL_CUTOFF: f64 = 0.45;
+
+/*
+ *   Blip buffer style resampler
+ *
+ *   Rather than sampling the mixer output (which aliases the sharp edges of
+ *   the square waves back into the audible range), the resampler records how
+ *   much the amplitude changes and when. Each change is added into a buffer of
+ *   differences as a windowed sinc impulse, positioned to the fraction of an
+ *   output sample at which it happened. Integrating that buffer then yields
+ *   band-limited steps at the output sample rate.
+ */
+pub struct Resampler {
+    // Output samples per input clock.
+    ratio: f64,
+    // Fraction of the current output sample that has elapsed.
+    position: f64,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    deltas: VecDeque<f32>,
+    integrator: f32,
+    last_amplitude: f32,
+    filter: OutputFilter,
+    samples: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: u32, sample_rate: u32) -> Self {
+        Resampler {
+            ratio: sample_rate as f64 / clock_rate as f64,
+            position: 0.0,
+            kernel: Self::build_kernel(),
+            deltas: VecDeque::from(vec![0.0; KERNEL_WIDTH]),
+            integrator: 0.0,
+            last_amplitude: 0.0,
+            filter: OutputFilter::new(sample_rate as f32),
+            samples: Vec::new(),
+        }
+    }
+
+    // Each phase holds the impulse response of a Blackman windowed sinc
+    // low-pass filter, shifted by phase / KERNEL_PHASES of a sample, and is
+    // normalised so a step always settles at exactly its full height.
+    fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+        let centre = (KERNEL_WIDTH / 2) as f64;
+        (0..KERNEL_PHASES)
+            .map(|phase| {
+                let offset = phase as f64 / KERNEL_PHASES as f64;
+                let impulse: Vec<f64> = (0..KERNEL_WIDTH)
+                    .map(|tap| {
+                        let x = tap as f64 - centre - offset;
+                        let sinc = match x == 0.0 {
+                            true => 1.0,
+                            false => {
+                                let t = PI * 2.0 * KERNEL_CUTOFF * x;
+                                t.sin() / t
+                            }
+                        };
+                        // Blackman window spanning the kernel once shifted.
+                        let n = (x + centre + 1.0) / (KERNEL_WIDTH as f64 + 1.0);
+                        let window =
+                            0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+                        sinc * window.max(0.0)
+                    })
+                    .collect();
+                let sum: f64 = impulse.iter().sum();
+                let mut taps = [0.0; KERNEL_WIDTH];
+                for (tap, value) in taps.iter_mut().zip(impulse.iter()) {
+                    *tap = (value / sum) as f32;
+                }
+                taps
+            })
+            .collect()
+    }
+
+    // Called once per input clock with the current mixer output.
+    pub fn clock(&mut self, amplitude: f32) {
+        let delta = amplitude - self.last_amplitude;
+        if delta != 0.0 {
+            self.last_amplitude = amplitude;
+            let phase = (self.position * KERNEL_PHASES as f64) as usize;
+            for (value, tap) in self.deltas.iter_mut().zip(self.kernel[phase].iter()) {
+                *value += delta * tap;
+            }
+        }
+
+        self.position += self.ratio;
+        if self.position >= 1.0 {
+            self.position -= 1.0;
+            self.integrator += self.deltas.pop_front().unwrap_or(0.0);
+            self.deltas.push_back(0.0);
+            let sample = self.filter.process(self.integrator);
+            self.samples.push(sample);
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
@@ -0,0 +1,120 @@
+use super::length_counter::LengthCounter;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// https://www.nesdev.org/wiki/APU_Triangle
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+pub struct Triangle {
+    pub length_counter: LengthCounter,
+    control: bool,
+    linear_counter: u8,
+    linear_counter_period: u8,
+    linear_counter_reload: bool,
+    sequence_step: u8,
+    timer: u16,
+    timer_period: u16,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            length_counter: LengthCounter::new(),
+            control: false,
+            linear_counter: 0,
+            linear_counter_period: 0,
+            linear_counter_reload: false,
+            sequence_step: 0,
+            timer: 0,
+            timer_period: 0,
+        }
+    }
+
+    /*
+     *   0x4008   CRRR RRRR   Length counter halt / linear counter control, reload value
+     *   0x400A   TTTT TTTT   Timer low
+     *   0x400B   LLLL LTTT   Length counter load, timer high
+     */
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = value & 0b10000000 != 0;
+                self.length_counter.halt = self.control;
+                self.linear_counter_period = value & 0b1111111;
+            }
+            1 => {}
+            2 => {
+                self.timer_period = (self.timer_period & 0x0700) | value as u16;
+            }
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0b111) << 8);
+                self.length_counter.load(value >> 3);
+                self.linear_counter_reload = true;
+            }
+        }
+    }
+
+    // Unlike the other channels the triangle timer runs at the CPU clock.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            // The sequencer only steps while both counters are non-zero.
+            if self.linear_counter > 0 && self.length_counter.is_active() {
+                self.sequence_step = (self.sequence_step + 1) & 0b11111;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Clocked by quarter frames of the frame counter.
+    pub fn clock_linear_counter(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_period;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    // Halting the sequencer leaves the output at its last value rather than
+    // dropping to zero, which avoids pops.
+    pub fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence_step as usize]
+    }
+}
+
+impl Default for Triangle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.length_counter.save_state(state);
+        state.write_bool(self.control);
+        state.write_u8(self.linear_counter);
+        state.write_u8(self.linear_counter_period);
+        state.write_bool(self.linear_counter_reload);
+        state.write_u8(self.sequence_step);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.length_counter.load_state(state)?;
+        self.control = state.read_bool()?;
+        self.linear_counter = state.read_u8()?;
+        self.linear_counter_period = state.read_u8()?;
+        self.linear_counter_reload = state.read_bool()?;
+        self.sequence_step = state.read_u8()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        Ok(())
+    }
+}
@@ -1,66 +1,135 @@
 #![allow(warnings)]
 
+use crate::apu::APU;
 use crate::cartridge::Cartridge;
 use crate::cpu::Memory;
 use crate::joypad::Joypad;
 use crate::ppu::PPU;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 const RAM_ADDRESS_SPACE_START: u16 = 0x0000;
 const RAM_ADDRESS_SPACE_END: u16 = 0x1FFF;
 const PPU_ADDRESS_SPACE_START: u16 = 0x2000;
 const PPU_ADDRESS_SPACE_END: u16 = 0x3FFF;
+const PRG_RAM_ADDRESS_SPACE_START: u16 = 0x6000;
+const PRG_RAM_ADDRESS_SPACE_END: u16 = 0x7FFF;
 const PRG_ADDRESS_SPACE_START: u16 = 0x8000;
 const PRG_ADDRESS_SPACE_END: u16 = 0xFFFF;
+const DMC_STALL_CYCLES: u8 = 4;
+// Controller reads only drive the low bits of the data bus. The rest keep
+// the last value on the bus, which is the high byte of the address, 0x40.
+const CONTROLLER_OPEN_BUS: u8 = 0x40;
 
-pub struct Bus<'call> {
+pub struct Bus {
     vram: [u8; 0x800],
     cartridge: Cartridge,
     ppu: PPU,
+    apu: APU,
     cycles: usize,
-    callback: Box<dyn FnMut(&PPU, &mut Joypad) + 'call>,
-    joypad: Joypad,
+    joypads: [Joypad; 2],
 }
 
-impl<'a> Bus<'a> {
-    pub fn new<'call, F>(cart: Cartridge, callback: F) -> Bus<'call>
-    where
-        F: FnMut(&PPU, &mut Joypad) + 'call,
-    {
-        let ppu = PPU::new(cart.rom_chr.clone(), cart.mirroring_type.clone());
+impl Bus {
+    pub fn new(cart: Cartridge) -> Bus {
+        let ppu = PPU::new(cart.mapper.clone());
         Bus {
             vram: [0; 0x800],
             cartridge: cart,
             ppu,
+            apu: APU::new(),
             cycles: 0,
-            callback: Box::from(callback),
-            joypad: Joypad::new(),
+            joypads: [Joypad::new(), Joypad::new()],
         }
     }
 
+    pub fn set_audio_output(&mut self, clock_rate: u32, sample_rate: u32) {
+        self.apu.set_output(clock_rate, sample_rate);
+    }
+
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.ppu.sprite_limit = enabled;
+    }
+
     pub fn tick(&mut self, cycles: u8) {
         self.cycles += cycles as usize;
-        // Cheat way to render a screen is to read the screen state before
-        // the CPu starts to render a new frame.
-        let nmi_prior = self.ppu.nmi_interrupt.is_some();
         self.ppu.tick(cycles * 3);
-        let nmi_after = self.ppu.nmi_interrupt.is_some();
-        if !nmi_prior && nmi_after {
-            (self.callback)(&self.ppu, &mut self.joypad);
+        self.apu.tick(cycles);
+        // The DMC fetches samples from CPU memory itself, stalling the CPU
+        // for up to four cycles while it holds the bus.
+        if let Some(address) = self.apu.dmc.sample_address() {
+            let sample = self.mem_read(address);
+            self.apu.dmc.load_sample(sample);
+            self.tick(DMC_STALL_CYCLES);
         }
     }
+
+    // CPU cycles elapsed since power on.
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    // Port 0 is read at 0x4016 and port 1 at 0x4017.
+    pub fn joypad_mut(&mut self, port: usize) -> &mut Joypad {
+        &mut self.joypads[port]
+    }
+
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    // Whether the PPU has finished a frame since the last poll.
+    pub fn poll_frame_complete(&mut self) -> bool {
+        std::mem::take(&mut self.ppu.frame_complete)
+    }
+
     pub fn poll_nmi_status(&mut self) -> Option<u8> {
         self.ppu.nmi_interrupt.take()
     }
+    // Unlike NMI, the IRQ line is level triggered and stays asserted until
+    // the source acknowledges it.
+    pub fn poll_irq_status(&self) -> bool {
+        self.cartridge.mapper.borrow().irq_pending() || self.apu.irq_pending()
+    }
 }
 
-impl Memory for Bus<'_> {
-    fn mem_write_u32(&mut self, addr: u32, value: u8) {
-        if addr <= 0x2000 {
-            self.cartridge.rom_chr[addr as usize] = value;
+impl Snapshot for Bus {
+    fn save_state(&self, state: &mut StateWriter) {
+        // Enough of the header to catch a state being loaded into another
+        // game, which would otherwise fail in confusing ways.
+        state.write_u16(self.cartridge.header.mapper);
+        state.write_usize(self.cartridge.header.prg_rom_size);
+        state.write_usize(self.cartridge.header.chr_rom_size);
+        state.write_bytes(&self.vram);
+        state.write_usize(self.cycles);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+        self.joypads[0].save_state(state);
+        self.joypads[1].save_state(state);
+        self.cartridge.mapper.borrow().save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if state.read_u16()? != self.cartridge.header.mapper
+            || state.read_usize()? != self.cartridge.header.prg_rom_size
+            || state.read_usize()? != self.cartridge.header.chr_rom_size
+        {
+            return Err(StateError::CartridgeMismatch);
         }
-        self.cartridge.rom_prg[addr as usize] = value
+        state.read_bytes(&mut self.vram)?;
+        self.cycles = state.read_usize()?;
+        self.ppu.load_state(state)?;
+        self.apu.load_state(state)?;
+        self.joypads[0].load_state(state)?;
+        self.joypads[1].load_state(state)?;
+        self.cartridge.mapper.borrow_mut().load_state(state)
     }
+}
 
+impl Memory for Bus {
     fn mem_read(&mut self, addr: u16) -> u8 {
         match addr {
             RAM_ADDRESS_SPACE_START..=RAM_ADDRESS_SPACE_END => {
@@ -81,14 +150,15 @@ impl Memory for Bus<'_> {
                 let mirror_down = addr & 0x2007;
                 self.mem_read(mirror_down)
             }
-            0x4016 => self.joypad.read(),
-            0x4000..=0x4015 | 0x4017 => 0,
+            0x4016 => CONTROLLER_OPEN_BUS | self.joypads[0].read(),
+            0x4017 => CONTROLLER_OPEN_BUS | self.joypads[1].read(),
+            0x4015 => self.apu.read_status(),
+            0x4000..=0x4013 => 0,
+            PRG_RAM_ADDRESS_SPACE_START..=PRG_RAM_ADDRESS_SPACE_END => {
+                self.cartridge.mapper.borrow().cpu_read(addr)
+            }
             PRG_ADDRESS_SPACE_START..=PRG_ADDRESS_SPACE_END => {
-                let rom_address = self.cartridge.mapper.map_prg(addr);
-                if self.cartridge.rom_prg.len() == 0x4000 && rom_address >= 0x4000 {
-                    return self.cartridge.rom_prg[(rom_address % 0x4000) as usize];
-                }
-                self.cartridge.rom_prg[rom_address as usize]
+                self.cartridge.mapper.borrow().cpu_read(addr)
             }
             _ => {
                 println!("Memory access at {:#04X?} ignored", addr);
@@ -126,7 +196,9 @@ impl Memory for Bus<'_> {
             0x2007 => {
                 self.ppu.write_data(value);
             }
-            0x4000..=0x4013 | 0x4015 | 0x4017 => {}
+            0x4000..=0x4013 | 0x4015 | 0x4017 => {
+                self.apu.write_register(addr, value);
+            }
             0x4014 => {
                 // OAMDMA
                 // Writing anyhting to this register sends 0xNN00 -> 0xNNFF to
@@ -141,15 +213,22 @@ impl Memory for Bus<'_> {
                 }
                 self.ppu.write_to_oam_dma(&buffer);
             }
+            // Both controllers share the strobe line driven from 0x4016.
             0x4016 => {
-                self.joypad.write(value);
+                self.joypads[0].write(value);
+                self.joypads[1].write(value);
             }
             PPU_ADDRESS_SPACE_START..=PPU_ADDRESS_SPACE_END => {
                 let mirror_down = addr & 0x2007;
                 self.mem_write(mirror_down, value);
             }
+            PRG_RAM_ADDRESS_SPACE_START..=PRG_RAM_ADDRESS_SPACE_END => {
+                self.cartridge.mapper.borrow_mut().cpu_write(addr, value);
+            }
             PRG_ADDRESS_SPACE_START..=PRG_ADDRESS_SPACE_END => {
-                self.mem_write_u32(self.cartridge.mapper.map_prg(addr), value);
+                // PRG ROM is read-only, writes to this space are picked up
+                // by the mapper's registers instead.
+                self.cartridge.mapper.borrow_mut().cpu_write(addr, value);
             }
             _ => {
                 println!("Memory write at {:#04X?} ignored", addr);
@@ -1,85 +1,236 @@
+pub mod error;
+pub mod header;
+
+use crate::mapper::mmc1::MMC1;
+use crate::mapper::mmc3::MMC3;
 use crate::mapper::nrom::NROM;
 use crate::mapper::uxrom::UXROM;
 use crate::mapper::Mapper;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+pub use error::CartridgeError;
+pub use header::{CartridgeHeader, ConsoleType, HeaderFormat, TimingMode};
 
-const NES_IDENTIFIER_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const ROM_BANK_SIZE: usize = 16384;
 const VROM_BANK_SIZE: usize = 8192;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MirroringType {
     Vertical,
     Horizontal,
     FourScreen,
+    SingleScreenLower,
+    SingleScreenUpper,
 }
 
-pub struct Cartridge {
-    pub rom_prg: Vec<u8>,
-    pub rom_chr: Vec<u8>,
-    pub mapper_type: u8,
-    pub mapper: Box<dyn Mapper>,
-    pub mirroring_type: MirroringType,
+// Pattern table memory on the cartridge. Boards with no CHR ROM banks carry
+// 8KB of CHR RAM instead, which the PPU is free to write to.
+pub struct ChrMemory {
+    data: Vec<u8>,
+    is_ram: bool,
 }
 
-impl Cartridge {
-    pub fn new(binary: &[u8]) -> Result<Cartridge, String> {
-        // Define location of control bytes.
-        let control_byte_one = binary[6];
-        let control_byte_two = binary[7];
+impl ChrMemory {
+    pub fn rom(data: Vec<u8>) -> Self {
+        ChrMemory {
+            data,
+            is_ram: false,
+        }
+    }
 
-        // Get cartridge mapper.
-        let mapper_value = control_byte_two & 0b11110000 | control_byte_one >> 4;
+    pub fn ram(size: usize) -> Self {
+        ChrMemory {
+            data: vec![0; size],
+            is_ram: true,
+        }
+    }
 
-        // Determine whether binary is identifiable as an NES cartridge.
-        if (binary[0..4]) != NES_IDENTIFIER_TAG {
-            return Err("File is in incorrect format".to_string());
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn read(&self, address: u32) -> u8 {
+        self.data[address as usize]
+    }
+
+    pub fn write(&mut self, address: u32, value: u8) {
+        if self.is_ram {
+            self.data[address as usize] = value;
         }
+    }
+}
 
-        // Get the count of 8KB ROM and 16KB VROM banks. Multiply by
-        // page size to get total size.
-        let rom_prg_size = binary[4] as usize * ROM_BANK_SIZE;
-        let rom_chr_size = binary[5] as usize * VROM_BANK_SIZE;
+// Only CHR RAM is part of a save state, CHR ROM can't change.
+impl Snapshot for ChrMemory {
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.is_ram {
+            state.write_vec(&self.data);
+        }
+    }
 
-        // Evaluate whether trainer data is used.
-        let is_trainer = (control_byte_one & 0b100) != 0;
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.is_ram {
+            state.read_vec(&mut self.data)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    pub mapper: Rc<RefCell<dyn Mapper>>,
+}
+
+impl Cartridge {
+    pub fn new(binary: &[u8]) -> Result<Cartridge, CartridgeError> {
+        let header = CartridgeHeader::parse(binary)?;
+        if binary.len() < header.file_size() {
+            return Err(CartridgeError::Truncated {
+                expected: header.file_size(),
+                actual: binary.len(),
+            });
+        }
 
         // Lower bounds of addressable space for both PRG and CHR ROM.
-        let rom_prg_start = 16 + if is_trainer { 512 } else { 0 };
-        let rom_chr_start = rom_prg_start + rom_prg_size;
-
-        // Determine the mirroring type of the cartridge
-        let mirroring_plane = (control_byte_one & 0b1) != 0;
-        let mirroring_four_screen = (control_byte_one & 0b1000) != 0;
-        let mirroring = match (mirroring_four_screen, mirroring_plane) {
-            (true, _) => MirroringType::FourScreen,
-            (false, true) => MirroringType::Vertical,
-            (false, false) => MirroringType::Horizontal,
+        let rom_prg_start = 16 + if header.trainer { 512 } else { 0 };
+        let rom_chr_start = rom_prg_start + header.prg_rom_size;
+
+        let rom_prg = binary[rom_prg_start..(rom_prg_start + header.prg_rom_size)].to_vec();
+        let rom_chr = match header.chr_rom_size {
+            // NES 2.0 headers may omit the CHR RAM size, assume the usual 8KB.
+            0 => ChrMemory::ram(header.total_chr_ram_size().max(VROM_BANK_SIZE)),
+            size => ChrMemory::rom(binary[rom_chr_start..(rom_chr_start + size)].to_vec()),
         };
 
-        let prg_banks = binary[4];
+        check_prg_rom_size(header.mapper, header.prg_rom_size)?;
+        check_chr_rom_size(header.mapper, header.chr_rom_size)?;
 
-        let map: Box<dyn Mapper> = match mapper_value {
+        let prg_ram_size = header.total_prg_ram_size();
+        let mirroring = header.mirroring;
+        let map: Rc<RefCell<dyn Mapper>> = match header.mapper {
             0x00 => {
-                Box::new(NROM::new())
+                Rc::new(RefCell::new(NROM::new(rom_prg, rom_chr, prg_ram_size, mirroring)))
+            }
+            0x01 => {
+                Rc::new(RefCell::new(MMC1::new(rom_prg, rom_chr, prg_ram_size)))
             }
             0x02 => {
-                Box::new(UXROM::new(prg_banks))
+                Rc::new(RefCell::new(UXROM::new(rom_prg, rom_chr, mirroring)))
             }
-            _ => {
-                todo!("Mappers")
+            0x04 => {
+                Rc::new(RefCell::new(MMC3::new(rom_prg, rom_chr, prg_ram_size, mirroring)))
+            }
+            mapper => {
+                return Err(CartridgeError::UnsupportedMapper(mapper));
             }
         };
 
         Ok(Cartridge {
-            rom_prg: binary[rom_prg_start..(rom_prg_start + rom_prg_size)].to_vec(),
-            rom_chr: binary[rom_chr_start..(rom_chr_start + rom_chr_size)].to_vec(),
-            mapper_type: mapper_value,
+            header,
             mapper: map,
-            mirroring_type: mirroring,
         })
     }
 }
 
+/*
+ *   The mappers switch PRG ROM in banks and fix the last one (MMC3 the last
+ *   two) in place, so the ROM has to hold at least that many whole banks.
+ *   NROM just mirrors whatever it has.
+ *
+ *   Mapper   Bank size   Minimum banks
+ *   UxROM    16KB        1
+ *   MMC1     16KB        1
+ *   MMC3     8KB         2
+ */
+fn check_prg_rom_size(mapper: u16, size: usize) -> Result<(), CartridgeError> {
+    let (bank_size, minimum_banks) = match mapper {
+        0x01 | 0x02 => (ROM_BANK_SIZE, 1),
+        0x04 => (ROM_BANK_SIZE / 2, 2),
+        _ => return Ok(()),
+    };
+    if !size.is_multiple_of(bank_size) || size / bank_size < minimum_banks {
+        return Err(CartridgeError::InvalidPrgRomSize { mapper, size });
+    }
+    Ok(())
+}
+
+/*
+ *   CHR ROM has to fill the pattern table window the mapper switches in
+ *   one go: all 8KB on boards without CHR banking. Boards with no CHR ROM
+ *   get at least 8KB of CHR RAM instead, which always fits.
+ *
+ *   Mapper   Bank size   Minimum banks
+ *   NROM     8KB         1
+ *   UxROM    8KB         1
+ *   MMC1     4KB         1
+ *   MMC3     1KB         1
+ */
+fn check_chr_rom_size(mapper: u16, size: usize) -> Result<(), CartridgeError> {
+    let bank_size = match mapper {
+        0x00 | 0x02 => VROM_BANK_SIZE,
+        0x01 => 0x1000,
+        0x04 => 0x400,
+        _ => return Ok(()),
+    };
+    if size != 0 && (!size.is_multiple_of(bank_size) || size < bank_size) {
+        return Err(CartridgeError::InvalidChrRomSize { mapper, size });
+    }
+    Ok(())
+}
+
+// Battery backed PRG RAM is persisted to a .sav file next to the ROM.
+pub struct SaveFile {
+    path: PathBuf,
+    mapper: Rc<RefCell<dyn Mapper>>,
+    saved: Vec<u8>,
+}
+
+impl SaveFile {
+    pub fn new(rom_path: &Path, cartridge: &Cartridge) -> Self {
+        // Start from the power on contents so there's nothing to flush until
+        // the game writes to PRG RAM.
+        let saved = cartridge.mapper.borrow().prg_ram().to_vec();
+        SaveFile {
+            path: rom_path.with_extension("sav"),
+            mapper: cartridge.mapper.clone(),
+            saved,
+        }
+    }
+
+    // Copy a previous save into PRG RAM, if one exists.
+    pub fn load(&mut self) -> std::io::Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        let data = std::fs::read(&self.path)?;
+        let mut mapper = self.mapper.borrow_mut();
+        let prg_ram = mapper.prg_ram_mut();
+        let length = data.len().min(prg_ram.len());
+        prg_ram[..length].copy_from_slice(&data[..length]);
+        self.saved = prg_ram.to_vec();
+        Ok(())
+    }
+
+    // Write PRG RAM out to disk. Skipped when nothing has changed since the
+    // last flush so this can be called regularly.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        let mapper = self.mapper.borrow();
+        let prg_ram = mapper.prg_ram();
+        if prg_ram.is_empty() || prg_ram == self.saved.as_slice() {
+            return Ok(());
+        }
+        std::fs::write(&self.path, prg_ram)?;
+        self.saved = prg_ram.to_vec();
+        Ok(())
+    }
+}
+
 pub mod test {
     use super::*;
     struct TestROM {
@@ -120,3 +271,105 @@ pub mod test {
         Cartridge::new(&test_rom).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An iNES image for `mapper` with the given PRG and CHR ROM sizes in
+    // bytes, described in NES 2.0 exponent-multiplier form so any size can
+    // be given.
+    fn image(mapper: u8, prg_size: usize, chr_size: usize) -> Vec<u8> {
+        let mut binary = vec![0x4E, 0x45, 0x53, 0x1A, 0, 0, mapper << 4, 0b1000, 0, 0x0F];
+        binary[4] = size_field(prg_size);
+        // Exponent form can't give a size of 0.
+        if chr_size > 0 {
+            binary[5] = size_field(chr_size);
+            binary[9] |= 0xF0;
+        }
+        binary.resize(16, 0);
+        binary.resize(16 + prg_size + chr_size, 0);
+        binary
+    }
+
+    // Only powers of two are needed here, so the multiplier is always 1.
+    fn size_field(size: usize) -> u8 {
+        (size.trailing_zeros() as u8) << 2
+    }
+
+    fn error(binary: &[u8]) -> Option<CartridgeError> {
+        Cartridge::new(binary).err()
+    }
+
+    #[test]
+    fn loads_supported_mappers() {
+        for mapper in [0, 1, 2, 4] {
+            let cartridge = Cartridge::new(&image(mapper, 0x8000, 0x2000)).unwrap();
+            assert_eq!(cartridge.header.mapper, mapper as u16);
+        }
+        // No CHR ROM means CHR RAM.
+        let cartridge = Cartridge::new(&image(2, 0x20000, 0)).unwrap();
+        let mut mapper = cartridge.mapper.borrow_mut();
+        mapper.ppu_write(0x1FFF, 0x42);
+        assert_eq!(mapper.ppu_read(0x1FFF), 0x42);
+    }
+
+    #[test]
+    fn rejects_files_that_arent_roms() {
+        assert_eq!(error(b"NES\x1A"), Some(CartridgeError::TooShort));
+        assert_eq!(error(&[0; 32]), Some(CartridgeError::BadMagic));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let mut binary = image(0, 0x4000, 0x2000);
+        binary.truncate(binary.len() - 1);
+        assert_eq!(
+            error(&binary),
+            Some(CartridgeError::Truncated {
+                expected: 16 + 0x6000,
+                actual: 16 + 0x6000 - 1,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_mappers() {
+        assert_eq!(
+            error(&image(3, 0x8000, 0x2000)),
+            Some(CartridgeError::UnsupportedMapper(3))
+        );
+    }
+
+    #[test]
+    fn rejects_prg_rom_the_mapper_cant_bank() {
+        // NROM mirrors anything.
+        assert!(Cartridge::new(&image(0, 0x2000, 0x2000)).is_ok());
+        for (mapper, size) in [(1, 0x2000), (2, 0x2000), (4, 0x2000), (4, 0x1000)] {
+            assert_eq!(
+                error(&image(mapper, size, 0x2000)),
+                Some(CartridgeError::InvalidPrgRomSize {
+                    mapper: mapper as u16,
+                    size
+                })
+            );
+        }
+        assert!(Cartridge::new(&image(4, 0x4000, 0x2000)).is_ok());
+    }
+
+    #[test]
+    fn rejects_chr_rom_smaller_than_the_pattern_tables() {
+        for (mapper, size) in [(0, 0x400), (0, 0x1000), (2, 0x1000), (1, 0x800), (4, 0x200)] {
+            assert_eq!(
+                error(&image(mapper, 0x8000, size)),
+                Some(CartridgeError::InvalidChrRomSize {
+                    mapper: mapper as u16,
+                    size
+                })
+            );
+        }
+        // Banked CHR only needs a single bank.
+        assert!(Cartridge::new(&image(1, 0x8000, 0x1000)).is_ok());
+        assert!(Cartridge::new(&image(4, 0x8000, 0x400)).is_ok());
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CartridgeError {
+    // The file is smaller than the 16 byte header.
+    TooShort,
+    // The file doesn't start with "NES" followed by an MS-DOS EOF.
+    BadMagic,
+    // The header describes more data than the file contains.
+    Truncated { expected: usize, actual: usize },
+    UnsupportedMapper(u16),
+    // The PRG ROM doesn't fill the mapper's fixed banks, or isn't a whole
+    // number of its switchable banks.
+    InvalidPrgRomSize { mapper: u16, size: usize },
+    // The CHR ROM is smaller than the pattern tables, or isn't a whole
+    // number of the mapper's CHR banks.
+    InvalidChrRomSize { mapper: u16, size: usize },
+    InvalidHeader(&'static str),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::TooShort => write!(f, "file is too short to contain an NES header"),
+            CartridgeError::BadMagic => write!(f, "file is not in iNES format"),
+            CartridgeError::Truncated { expected, actual } => write!(
+                f,
+                "file is truncated, expected {} bytes but found {}",
+                expected, actual
+            ),
+            CartridgeError::UnsupportedMapper(mapper) => {
+                write!(f, "mapper {} is not supported", mapper)
+            }
+            CartridgeError::InvalidPrgRomSize { mapper, size } => {
+                write!(f, "mapper {} can't use {} bytes of PRG ROM", mapper, size)
+            }
+            CartridgeError::InvalidChrRomSize { mapper, size } => {
+                write!(f, "mapper {} can't use {} bytes of CHR ROM", mapper, size)
+            }
+            CartridgeError::InvalidHeader(reason) => write!(f, "invalid header: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
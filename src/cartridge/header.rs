@@ -0,0 +1,391 @@
+use super::{CartridgeError, MirroringType};
+
+const NES_IDENTIFIER_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const ROM_BANK_SIZE: usize = 16384;
+const VROM_BANK_SIZE: usize = 8192;
+const PRG_RAM_BANK_SIZE: usize = 8192;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HeaderFormat {
+    INes,
+    Nes2,
+}
+
+// CPU/PPU timing the cartridge was made for.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TimingMode {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    PlayChoice10,
+    // NES 2.0 extended console type, held in byte 13.
+    Extended(u8),
+}
+
+/*
+ *   https://www.nesdev.org/wiki/NES_2.0
+ *
+ *   Bytes 0 - 7 share their layout with iNES 1.0. A file is in NES 2.0
+ *   format when bits 2 and 3 of byte 7 are 0b10, in which case bytes 8 - 15
+ *   carry the extended fields below instead of (mostly unused) iNES data.
+ *
+ *   8  - Mapper MSB (bits 0-3) / submapper (bits 4-7)
+ *   9  - PRG ROM size MSB (bits 0-3) / CHR ROM size MSB (bits 4-7)
+ *   10 - PRG RAM shift count (bits 0-3) / PRG NVRAM shift count (bits 4-7)
+ *   11 - CHR RAM shift count (bits 0-3) / CHR NVRAM shift count (bits 4-7)
+ *   12 - CPU/PPU timing (bits 0-1)
+ *   13 - Vs. System type or extended console type
+ *   14 - Number of miscellaneous ROMs
+ *   15 - Default expansion device (bits 0-5)
+ */
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: MirroringType,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: TimingMode,
+    pub console_type: ConsoleType,
+    pub expansion_device: u8,
+}
+
+impl CartridgeHeader {
+    pub fn parse(binary: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if binary.len() < HEADER_SIZE {
+            return Err(CartridgeError::TooShort);
+        }
+        // Determine whether binary is identifiable as an NES cartridge.
+        if binary[0..4] != NES_IDENTIFIER_TAG {
+            return Err(CartridgeError::BadMagic);
+        }
+
+        // Define location of control bytes.
+        let control_byte_one = binary[6];
+        let control_byte_two = binary[7];
+
+        let format = match control_byte_two & 0b1100 {
+            0b1000 => HeaderFormat::Nes2,
+            _ => HeaderFormat::INes,
+        };
+
+        // Determine the mirroring type of the cartridge
+        let mirroring_plane = (control_byte_one & 0b1) != 0;
+        let mirroring_four_screen = (control_byte_one & 0b1000) != 0;
+        let mirroring = match (mirroring_four_screen, mirroring_plane) {
+            (true, _) => MirroringType::FourScreen,
+            (false, true) => MirroringType::Vertical,
+            (false, false) => MirroringType::Horizontal,
+        };
+
+        // Evaluate whether PRG RAM is battery backed and should persist.
+        let battery = (control_byte_one & 0b10) != 0;
+        // Evaluate whether trainer data is used.
+        let trainer = (control_byte_one & 0b100) != 0;
+
+        let console_type = match control_byte_two & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Extended(binary[13] & 0x0F),
+        };
+
+        let header = match format {
+            HeaderFormat::Nes2 => {
+                Self::parse_nes2(binary, mirroring, battery, trainer, console_type)
+            }
+            HeaderFormat::INes => {
+                Self::parse_ines(binary, mirroring, battery, trainer, console_type)
+            }
+        };
+        if header.prg_rom_size == 0 {
+            return Err(CartridgeError::InvalidHeader("no PRG ROM"));
+        }
+        Ok(header)
+    }
+
+    // Size in bytes of the header, trainer and ROM data the header describes.
+    pub fn file_size(&self) -> usize {
+        HEADER_SIZE
+            .saturating_add(if self.trainer { TRAINER_SIZE } else { 0 })
+            .saturating_add(self.prg_rom_size)
+            .saturating_add(self.chr_rom_size)
+    }
+
+    fn parse_ines(
+        binary: &[u8],
+        mirroring: MirroringType,
+        battery: bool,
+        trainer: bool,
+        console_type: ConsoleType,
+    ) -> CartridgeHeader {
+        // Old dumping tools wrote garbage (e.g. "DiskDude!") into bytes 7 -
+        // 15. When the tail of the header isn't zeroed, the upper mapper
+        // nibble in byte 7 can't be trusted either.
+        let mapper_high = match binary[12..16].iter().all(|b| *b == 0) {
+            true => binary[7] & 0b11110000,
+            false => 0,
+        };
+        let mapper = (mapper_high | binary[6] >> 4) as u16;
+
+        // A value of zero in byte 8 infers 8KB of PRG RAM for compatibility.
+        let prg_ram_size = (binary[8] as usize).max(1) * PRG_RAM_BANK_SIZE;
+        let (prg_ram_size, prg_nvram_size) = match battery {
+            true => (0, prg_ram_size),
+            false => (prg_ram_size, 0),
+        };
+
+        // Get the count of 16KB ROM and 8KB VROM banks. Multiply by
+        // page size to get total size.
+        let chr_rom_size = binary[5] as usize * VROM_BANK_SIZE;
+
+        CartridgeHeader {
+            format: HeaderFormat::INes,
+            mapper,
+            submapper: 0,
+            prg_rom_size: binary[4] as usize * ROM_BANK_SIZE,
+            chr_rom_size,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size: if chr_rom_size == 0 { VROM_BANK_SIZE } else { 0 },
+            chr_nvram_size: 0,
+            mirroring,
+            battery,
+            trainer,
+            timing: match binary[9] & 0b1 {
+                0 => TimingMode::Ntsc,
+                _ => TimingMode::Pal,
+            },
+            console_type,
+            expansion_device: 0,
+        }
+    }
+
+    fn parse_nes2(
+        binary: &[u8],
+        mirroring: MirroringType,
+        battery: bool,
+        trainer: bool,
+        console_type: ConsoleType,
+    ) -> CartridgeHeader {
+        let mapper = (binary[8] as u16 & 0x0F) << 8
+            | (binary[7] & 0b11110000) as u16
+            | (binary[6] >> 4) as u16;
+
+        CartridgeHeader {
+            format: HeaderFormat::Nes2,
+            mapper,
+            submapper: binary[8] >> 4,
+            prg_rom_size: Self::rom_size(binary[4], binary[9] & 0x0F, ROM_BANK_SIZE),
+            chr_rom_size: Self::rom_size(binary[5], binary[9] >> 4, VROM_BANK_SIZE),
+            prg_ram_size: Self::ram_size(binary[10] & 0x0F),
+            prg_nvram_size: Self::ram_size(binary[10] >> 4),
+            chr_ram_size: Self::ram_size(binary[11] & 0x0F),
+            chr_nvram_size: Self::ram_size(binary[11] >> 4),
+            mirroring,
+            battery,
+            trainer,
+            timing: match binary[12] & 0b11 {
+                0 => TimingMode::Ntsc,
+                1 => TimingMode::Pal,
+                2 => TimingMode::MultiRegion,
+                _ => TimingMode::Dendy,
+            },
+            console_type,
+            expansion_device: binary[15] & 0b111111,
+        }
+    }
+
+    // ROM sizes are normally a 12-bit count of banks. When the MSB nibble is
+    // 0xF the LSB byte instead holds the size in exponent-multiplier form,
+    // EEEEEEMM, giving a size of 2^E * (MM * 2 + 1) bytes.
+    fn rom_size(lsb: u8, msb: u8, bank_size: usize) -> usize {
+        if msb == 0x0F {
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = (lsb & 0b11) as usize * 2 + 1;
+            2usize.saturating_pow(exponent).saturating_mul(multiplier)
+        } else {
+            ((msb as usize) << 8 | lsb as usize) * bank_size
+        }
+    }
+
+    // RAM sizes are stored as a shift count, 64 << shift bytes, where a
+    // shift count of zero means there is no RAM of that kind.
+    fn ram_size(shift: u8) -> usize {
+        match shift {
+            0 => 0,
+            _ => 64 << shift,
+        }
+    }
+
+    // Total PRG RAM at 0x6000 - 0x7FFF, whether battery backed or not.
+    pub fn total_prg_ram_size(&self) -> usize {
+        self.prg_ram_size + self.prg_nvram_size
+    }
+
+    // Total CHR RAM, used in place of CHR ROM when the board has none.
+    pub fn total_chr_ram_size(&self) -> usize {
+        self.chr_ram_size + self.chr_nvram_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: [u8; 12]) -> [u8; 16] {
+        let mut header = [0; 16];
+        header[..4].copy_from_slice(&NES_IDENTIFIER_TAG);
+        header[4..].copy_from_slice(&bytes);
+        header
+    }
+
+    fn parse(bytes: [u8; 12]) -> CartridgeHeader {
+        CartridgeHeader::parse(&header(bytes)).unwrap()
+    }
+
+    #[test]
+    fn parses_ines() {
+        let header = parse([2, 1, 0x17, 0x40, 0, 0x01, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 0x41);
+        assert_eq!(header.prg_rom_size, 2 * ROM_BANK_SIZE);
+        assert_eq!(header.chr_rom_size, VROM_BANK_SIZE);
+        assert_eq!(header.chr_ram_size, 0);
+        assert_eq!(header.mirroring, MirroringType::Vertical);
+        assert!(header.battery);
+        assert!(header.trainer);
+        // Byte 8 of zero still means 8KB, battery backed here.
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, PRG_RAM_BANK_SIZE);
+        assert_eq!(header.timing, TimingMode::Pal);
+        assert_eq!(header.console_type, ConsoleType::Nes);
+        assert_eq!(
+            header.file_size(),
+            16 + 512 + 2 * ROM_BANK_SIZE + VROM_BANK_SIZE
+        );
+    }
+
+    #[test]
+    fn ines_without_chr_rom_has_chr_ram() {
+        let header = parse([1, 0, 0x00, 0x00, 2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.mirroring, MirroringType::Horizontal);
+        assert_eq!(header.chr_ram_size, VROM_BANK_SIZE);
+        assert_eq!(header.prg_ram_size, 2 * PRG_RAM_BANK_SIZE);
+        assert_eq!(header.timing, TimingMode::Ntsc);
+    }
+
+    #[test]
+    fn ignores_mapper_high_nibble_after_garbage() {
+        let header = parse([1, 1, 0x10, 0x40, 0, 0, 0, 0, b'D', b'u', b'd', b'e']);
+        assert_eq!(header.mapper, 0x01);
+    }
+
+    #[test]
+    fn four_screen_overrides_mirroring() {
+        let header = parse([1, 1, 0b1001, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.mirroring, MirroringType::FourScreen);
+    }
+
+    #[test]
+    fn parses_console_types() {
+        let console_type =
+            |flags: u8| parse([1, 1, 0, flags, 0, 0, 0, 0, 0, 0x0B, 0, 0]).console_type;
+        assert_eq!(console_type(0), ConsoleType::Nes);
+        assert_eq!(console_type(1), ConsoleType::VsSystem);
+        assert_eq!(console_type(2), ConsoleType::PlayChoice10);
+        assert_eq!(console_type(0b1011), ConsoleType::Extended(0x0B));
+    }
+
+    #[test]
+    fn parses_nes2() {
+        let header = parse([
+            0x02, 0x01, 0x42, 0x58, 0x31, 0x21, 0x97, 0x07, 0x03, 0, 0, 0x2A,
+        ]);
+        assert_eq!(header.format, HeaderFormat::Nes2);
+        assert_eq!(header.mapper, 0x154);
+        assert_eq!(header.submapper, 3);
+        // The MSB nibbles of byte 9 are whole banks.
+        assert_eq!(header.prg_rom_size, 0x102 * ROM_BANK_SIZE);
+        assert_eq!(header.chr_rom_size, 0x201 * VROM_BANK_SIZE);
+        assert_eq!(header.mirroring, MirroringType::Horizontal);
+        assert!(header.battery);
+        // 64 << shift, with a shift of 0 meaning none.
+        assert_eq!(header.prg_ram_size, 64 << 7);
+        assert_eq!(header.prg_nvram_size, 64 << 9);
+        assert_eq!(header.chr_ram_size, 64 << 7);
+        assert_eq!(header.chr_nvram_size, 0);
+        assert_eq!(header.total_prg_ram_size(), (64 << 7) + (64 << 9));
+        assert_eq!(header.timing, TimingMode::Dendy);
+        assert_eq!(header.expansion_device, 0x2A);
+    }
+
+    #[test]
+    fn parses_nes2_timing() {
+        let timing = |byte: u8| parse([1, 1, 0, 0x08, 0, 0, 0, 0, byte, 0, 0, 0]).timing;
+        assert_eq!(timing(0), TimingMode::Ntsc);
+        assert_eq!(timing(1), TimingMode::Pal);
+        assert_eq!(timing(2), TimingMode::MultiRegion);
+        assert_eq!(timing(3), TimingMode::Dendy);
+    }
+
+    #[test]
+    fn parses_nes2_exponent_sizes() {
+        // 2^10 * 3 bytes of PRG ROM and 2^13 * 1 bytes of CHR ROM.
+        let header = parse([
+            (10 << 2) | 0b01,
+            13 << 2,
+            0,
+            0x08,
+            0,
+            0xFF,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        assert_eq!(header.prg_rom_size, 3 * 1024);
+        assert_eq!(header.chr_rom_size, 8 * 1024);
+        // Sizes too large to hold saturate, so the file comes up truncated.
+        assert_eq!(
+            CartridgeHeader::rom_size(0xFF, 0x0F, ROM_BANK_SIZE),
+            usize::MAX
+        );
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert_eq!(
+            CartridgeHeader::parse(&header([0; 12])[..15]).err(),
+            Some(CartridgeError::TooShort)
+        );
+        let mut bad_magic = header([1; 12]);
+        bad_magic[3] = 0x1B;
+        assert_eq!(
+            CartridgeHeader::parse(&bad_magic).err(),
+            Some(CartridgeError::BadMagic)
+        );
+        assert_eq!(
+            CartridgeHeader::parse(&header([0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])).err(),
+            Some(CartridgeError::InvalidHeader("no PRG ROM"))
+        );
+    }
+}
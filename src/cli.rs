@@ -0,0 +1,331 @@
+use nesemu::cartridge::TimingMode;
+use std::fmt;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: nesemu [OPTIONS] <ROM>
+
+Options:
+    --scale <N>         Window scale factor (default 3)
+    --palette <FILE>    Load the colour palette from a .hex file
+    --fullscreen        Start in fullscreen
+    --region <REGION>   Force ntsc, pal or dendy timing instead of using the ROM header
+    --headless          Run without a window or audio, for --frames frames
+    --frames <N>        Quit after N frames (required with --headless)
+    --mute              Disable audio output
+    --no-sprite-limit   Draw every sprite instead of 8 a line, removing flicker
+    --config <FILE>     Load key, hotkey and gamepad bindings from a file
+    --record <FILE>     Record input to an FM2 movie
+    --record-from <N>   Start the recording from save state slot N
+    --play <FILE>       Play back input from an FM2 movie
+    -h, --help          Print this message";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliError {
+    MissingRom,
+    MissingValue(String),
+    InvalidValue { option: String, value: String },
+    UnknownOption(String),
+    UnexpectedArgument(String),
+    // The first option only works along with the second.
+    Requires(String, String),
+    // The two options can't both be given.
+    Conflicts(String, String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::MissingRom => write!(f, "no ROM file given"),
+            CliError::MissingValue(option) => write!(f, "{} expects a value", option),
+            CliError::InvalidValue { option, value } => {
+                write!(f, "invalid value '{}' for {}", value, option)
+            }
+            CliError::UnknownOption(option) => write!(f, "unknown option {}", option),
+            CliError::UnexpectedArgument(argument) => {
+                write!(f, "unexpected argument '{}'", argument)
+            }
+            CliError::Requires(option, other) => write!(f, "{} requires {}", option, other),
+            CliError::Conflicts(option, other) => {
+                write!(f, "{} can't be used with {}", option, other)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub scale: u32,
+    pub palette: Option<PathBuf>,
+    pub fullscreen: bool,
+    // Overrides the timing mode from the ROM header.
+    pub region: Option<TimingMode>,
+    pub headless: bool,
+    pub frames: Option<usize>,
+    pub mute: bool,
+    pub no_sprite_limit: bool,
+    pub config: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    // Save state slot the recording starts from, instead of power on.
+    pub record_from: Option<u8>,
+    pub play: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Options {
+    pub fn parse<I>(args: I) -> Result<Options, CliError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut rom = None;
+        let mut options = Options {
+            rom: PathBuf::new(),
+            scale: 3,
+            palette: None,
+            fullscreen: false,
+            region: None,
+            headless: false,
+            frames: None,
+            mute: false,
+            no_sprite_limit: false,
+            config: None,
+            record: None,
+            record_from: None,
+            play: None,
+            help: false,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Accept both "--scale 2" and "--scale=2".
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if arg.starts_with("--") => (name.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || match inline_value {
+                Some(value) => Ok(value.to_string()),
+                None => args.next().ok_or(CliError::MissingValue(name.clone())),
+            };
+            match name.as_str() {
+                "-h" | "--help" => options.help = true,
+                "--scale" => options.scale = Self::parse_number(&name, value()?)?,
+                "--palette" => options.palette = Some(PathBuf::from(value()?)),
+                "--fullscreen" => options.fullscreen = true,
+                "--region" => {
+                    let region = value()?;
+                    options.region = Some(match region.to_lowercase().as_str() {
+                        "ntsc" => TimingMode::Ntsc,
+                        "pal" => TimingMode::Pal,
+                        "dendy" => TimingMode::Dendy,
+                        _ => {
+                            return Err(CliError::InvalidValue {
+                                option: name,
+                                value: region,
+                            })
+                        }
+                    });
+                }
+                "--headless" => options.headless = true,
+                "--frames" => options.frames = Some(Self::parse_number(&name, value()?)?),
+                "--mute" => options.mute = true,
+                "--no-sprite-limit" => options.no_sprite_limit = true,
+                // --keymap is the name from before hotkeys and gamepads.
+                "--config" | "--keymap" => options.config = Some(PathBuf::from(value()?)),
+                "--record" => options.record = Some(PathBuf::from(value()?)),
+                "--record-from" => {
+                    let slot = value()?;
+                    options.record_from = match slot.parse() {
+                        // Save state slots are numbered from 1.
+                        Ok(slot) if slot > 0 => Some(slot),
+                        _ => {
+                            return Err(CliError::InvalidValue {
+                                option: name,
+                                value: slot,
+                            })
+                        }
+                    };
+                }
+                "--play" => options.play = Some(PathBuf::from(value()?)),
+                _ if name.starts_with('-') && name.len() > 1 => {
+                    return Err(CliError::UnknownOption(name))
+                }
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(CliError::UnexpectedArgument(arg)),
+            }
+        }
+
+        match (rom, options.help) {
+            (Some(rom), _) => options.rom = rom,
+            (None, true) => {}
+            (None, false) => return Err(CliError::MissingRom),
+        }
+        if options.scale == 0 {
+            return Err(CliError::InvalidValue {
+                option: "--scale".to_string(),
+                value: "0".to_string(),
+            });
+        }
+        // There's no window to close, so a headless run needs an end.
+        if options.headless && options.frames.is_none() {
+            return Err(CliError::Requires(
+                "--headless".to_string(),
+                "--frames".to_string(),
+            ));
+        }
+        if options.record_from.is_some() {
+            if options.record.is_none() {
+                return Err(CliError::Requires(
+                    "--record-from".to_string(),
+                    "--record".to_string(),
+                ));
+            }
+            // A movie being played back has its own starting point.
+            if options.play.is_some() {
+                return Err(CliError::Conflicts(
+                    "--record-from".to_string(),
+                    "--play".to_string(),
+                ));
+            }
+        }
+        Ok(options)
+    }
+
+    fn parse_number<T: std::str::FromStr>(option: &str, value: String) -> Result<T, CliError> {
+        value.parse().map_err(|_| CliError::InvalidValue {
+            option: option.to_string(),
+            value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, CliError> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_rom_with_defaults() {
+        let options = parse(&["game.nes"]).unwrap();
+        assert_eq!(options.rom, PathBuf::from("game.nes"));
+        assert_eq!(options.scale, 3);
+        assert_eq!(options.region, None);
+        assert_eq!(options.frames, None);
+        assert!(!options.headless && !options.mute && !options.help);
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse(&[
+            "--scale",
+            "2",
+            "--region=PAL",
+            "--mute",
+            "--record",
+            "out.fm2",
+            "--record-from=3",
+            "game.nes",
+            "--headless",
+            "--frames=600",
+        ])
+        .unwrap();
+        assert_eq!(options.rom, PathBuf::from("game.nes"));
+        assert_eq!(options.scale, 2);
+        assert_eq!(options.region, Some(TimingMode::Pal));
+        assert!(options.mute);
+        assert_eq!(options.record, Some(PathBuf::from("out.fm2")));
+        assert_eq!(options.record_from, Some(3));
+        assert!(options.headless);
+        assert_eq!(options.frames, Some(600));
+    }
+
+    #[test]
+    fn help_doesnt_need_a_rom() {
+        assert!(parse(&["--help"]).unwrap().help);
+        assert!(parse(&["-h"]).unwrap().help);
+        assert_eq!(parse(&[]).err(), Some(CliError::MissingRom));
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert_eq!(
+            parse(&["--turbo", "game.nes"]).err(),
+            Some(CliError::UnknownOption("--turbo".to_string()))
+        );
+        assert_eq!(
+            parse(&["game.nes", "other.nes"]).err(),
+            Some(CliError::UnexpectedArgument("other.nes".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_missing_values() {
+        assert_eq!(
+            parse(&["game.nes", "--frames"]).err(),
+            Some(CliError::MissingValue("--frames".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let invalid = |option: &str, value: &str| {
+            Some(CliError::InvalidValue {
+                option: option.to_string(),
+                value: value.to_string(),
+            })
+        };
+        assert_eq!(
+            parse(&["game.nes", "--frames", "ten"]).err(),
+            invalid("--frames", "ten")
+        );
+        assert_eq!(
+            parse(&["game.nes", "--frames=-1"]).err(),
+            invalid("--frames", "-1")
+        );
+        assert_eq!(
+            parse(&["game.nes", "--scale", "0"]).err(),
+            invalid("--scale", "0")
+        );
+        assert_eq!(
+            parse(&["game.nes", "--region", "jp"]).err(),
+            invalid("--region", "jp")
+        );
+        assert_eq!(
+            parse(&["game.nes", "--record", "a.fm2", "--record-from", "0"]).err(),
+            invalid("--record-from", "0")
+        );
+    }
+
+    #[test]
+    fn checks_options_that_go_together() {
+        let requires = |option: &str, other: &str| {
+            Some(CliError::Requires(option.to_string(), other.to_string()))
+        };
+        assert_eq!(
+            parse(&["game.nes", "--headless"]).err(),
+            requires("--headless", "--frames")
+        );
+        assert_eq!(
+            parse(&["game.nes", "--record-from", "1"]).err(),
+            requires("--record-from", "--record")
+        );
+        assert_eq!(
+            parse(&[
+                "game.nes",
+                "--record=a.fm2",
+                "--record-from=1",
+                "--play=b.fm2"
+            ])
+            .err(),
+            Some(CliError::Conflicts(
+                "--record-from".to_string(),
+                "--play".to_string()
+            ))
+        );
+    }
+}
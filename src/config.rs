@@ -0,0 +1,408 @@
+use nesemu::joypad::JoypadButton;
+use sdl2::controller::Button;
+use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+// Each key presses a button on the controller in one port, 0 or 1.
+pub type Keymap = HashMap<Keycode, (usize, JoypadButton)>;
+// Hotkeys are looked up by key and whether shift is held.
+pub type Hotkeys = HashMap<(Keycode, bool), Action>;
+// Game controller buttons and the joypad buttons they press, one map for
+// the controller in each port.
+pub type ButtonMap = HashMap<Button, JoypadButton>;
+
+// How far the analog stick has to be pushed before it counts as the d-pad.
+const DEFAULT_DEADZONE: i16 = 8000;
+
+// Emulator actions which can be bound to a key.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+    Quit,
+    // Held to rewind.
+    Rewind,
+    Pause,
+    Reset,
+    ToggleFullscreen,
+    SaveState(u8),
+    LoadState(u8),
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Action> {
+        let (name, argument) = match name.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim())),
+            None => (name, None),
+        };
+        // Save state slots are numbered from 1.
+        let slot = argument
+            .and_then(|slot| slot.parse().ok())
+            .filter(|slot| *slot > 0);
+        match (name.to_lowercase().as_str(), argument) {
+            ("quit", None) => Some(Action::Quit),
+            ("rewind", None) => Some(Action::Rewind),
+            ("pause", None) => Some(Action::Pause),
+            ("reset", None) => Some(Action::Reset),
+            ("fullscreen", None) => Some(Action::ToggleFullscreen),
+            ("save_state", Some(_)) => slot.map(Action::SaveState),
+            ("load_state", Some(_)) => slot.map(Action::LoadState),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    // Line number (starting at 1) and a description of what's wrong with it.
+    InvalidLine(usize, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::InvalidLine(line, reason) => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub struct Config {
+    pub keymap: Keymap,
+    pub hotkeys: Hotkeys,
+    pub buttons: [ButtonMap; 2],
+    pub deadzone: i16,
+}
+
+impl Config {
+    // The hotkey bound to a key press, falling back to the unshifted binding
+    // when there's nothing bound with shift held.
+    pub fn hotkey(&self, keycode: Keycode, shift: bool) -> Option<Action> {
+        self.hotkeys
+            .get(&(keycode, shift))
+            .or_else(|| self.hotkeys.get(&(keycode, false)))
+            .copied()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            keymap: default_keymap(),
+            hotkeys: default_hotkeys(),
+            buttons: [default_buttons(), default_buttons()],
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+}
+
+pub fn default_keymap() -> Keymap {
+    let mut keymap = HashMap::new();
+    keymap.insert(Keycode::Down, (0, JoypadButton::DOWN));
+    keymap.insert(Keycode::Up, (0, JoypadButton::UP));
+    keymap.insert(Keycode::Right, (0, JoypadButton::RIGHT));
+    keymap.insert(Keycode::Left, (0, JoypadButton::LEFT));
+    keymap.insert(Keycode::Space, (0, JoypadButton::SELECT));
+    keymap.insert(Keycode::Return, (0, JoypadButton::START));
+    keymap.insert(Keycode::A, (0, JoypadButton::A));
+    keymap.insert(Keycode::S, (0, JoypadButton::B));
+
+    keymap.insert(Keycode::K, (1, JoypadButton::DOWN));
+    keymap.insert(Keycode::I, (1, JoypadButton::UP));
+    keymap.insert(Keycode::L, (1, JoypadButton::RIGHT));
+    keymap.insert(Keycode::J, (1, JoypadButton::LEFT));
+    keymap.insert(Keycode::Y, (1, JoypadButton::SELECT));
+    keymap.insert(Keycode::U, (1, JoypadButton::START));
+    keymap.insert(Keycode::N, (1, JoypadButton::A));
+    keymap.insert(Keycode::M, (1, JoypadButton::B));
+    keymap
+}
+
+pub fn default_hotkeys() -> Hotkeys {
+    let mut hotkeys = HashMap::new();
+    hotkeys.insert((Keycode::Escape, false), Action::Quit);
+    hotkeys.insert((Keycode::Backspace, false), Action::Rewind);
+    hotkeys.insert((Keycode::P, false), Action::Pause);
+    hotkeys.insert((Keycode::F12, false), Action::Reset);
+    hotkeys.insert((Keycode::F11, false), Action::ToggleFullscreen);
+    // F1 - F10 save to a slot, holding shift loads from it.
+    let slots = [
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+        Keycode::F6,
+        Keycode::F7,
+        Keycode::F8,
+        Keycode::F9,
+        Keycode::F10,
+    ];
+    for (slot, keycode) in (1..).zip(slots) {
+        hotkeys.insert((keycode, false), Action::SaveState(slot));
+        hotkeys.insert((keycode, true), Action::LoadState(slot));
+    }
+    hotkeys
+}
+
+// Laid out by position rather than label, so the NES's B and A sit where
+// they do on the original controller whatever the pad's face buttons say.
+pub fn default_buttons() -> ButtonMap {
+    let mut buttons = HashMap::new();
+    buttons.insert(Button::DPadDown, JoypadButton::DOWN);
+    buttons.insert(Button::DPadUp, JoypadButton::UP);
+    buttons.insert(Button::DPadRight, JoypadButton::RIGHT);
+    buttons.insert(Button::DPadLeft, JoypadButton::LEFT);
+    buttons.insert(Button::Back, JoypadButton::SELECT);
+    buttons.insert(Button::Start, JoypadButton::START);
+    buttons.insert(Button::B, JoypadButton::A);
+    buttons.insert(Button::A, JoypadButton::B);
+    buttons.insert(Button::Y, JoypadButton::B);
+    buttons.insert(Button::X, JoypadButton::A);
+    buttons
+}
+
+enum Section {
+    Player(usize),
+    Hotkeys,
+    // A port, or None for both.
+    Gamepad(Option<usize>),
+}
+
+/*
+ *   Config files hold one binding per line, grouped into sections. Blank
+ *   lines and lines starting with # are ignored.
+ *
+ *   [player1] / [player2]   An SDL key name followed by the joypad button it
+ *                           presses. Lines before any section are for player 1.
+ *   [hotkeys]               A key name, optionally prefixed with Shift+,
+ *                           followed by quit, rewind, pause, reset,
+ *                           fullscreen, save_state <N> or load_state <N>.
+ *   [gamepad1] / [gamepad2] An SDL game controller button name (a, b, x, y,
+ *                           back, start, dpup, ...) followed by a joypad
+ *                           button, or deadzone = <N> for the analog stick.
+ *   [gamepad]               The same, for the controllers in both ports.
+ *
+ *   # Arrow keys for the d-pad
+ *   Up = UP
+ *   Z = B
+ *   X = A
+ *
+ *   [player2]
+ *   I = UP
+ *
+ *   [hotkeys]
+ *   Shift+F1 = load_state 1
+ *
+ *   [gamepad]
+ *   deadzone = 10000
+ *
+ *   [gamepad2]
+ *   x = B
+ *
+ *   Bindings given in the file replace the defaults for that kind of
+ *   binding: keyboard, hotkey or each port's gamepad. Anything the file
+ *   doesn't mention keeps its default.
+ */
+pub fn load_config(path: &Path) -> Result<Config, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+    parse_config(&contents)
+}
+
+pub fn parse_config(contents: &str) -> Result<Config, ConfigError> {
+    let mut keymap = None;
+    let mut hotkeys = None;
+    let mut buttons: [Option<ButtonMap>; 2] = [None, None];
+    let mut deadzone = DEFAULT_DEADZONE;
+    let mut section = Section::Player(0);
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: String| ConfigError::InvalidLine(index + 1, reason);
+        if line.starts_with('[') {
+            section = match line {
+                "[player1]" => Section::Player(0),
+                "[player2]" => Section::Player(1),
+                "[hotkeys]" => Section::Hotkeys,
+                "[gamepad]" => Section::Gamepad(None),
+                "[gamepad1]" => Section::Gamepad(Some(0)),
+                "[gamepad2]" => Section::Gamepad(Some(1)),
+                _ => return Err(invalid(format!("unknown section '{}'", line))),
+            };
+            continue;
+        }
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("expected <name> = <value>, found '{}'", line)))?;
+        let (name, value) = (name.trim(), value.trim());
+        let joypad_button = || {
+            JoypadButton::from_name(&value.to_uppercase())
+                .ok_or_else(|| invalid(format!("unknown button '{}'", value)))
+        };
+        match section {
+            Section::Player(port) => {
+                let keycode = Keycode::from_name(name)
+                    .ok_or_else(|| invalid(format!("unknown key '{}'", name)))?;
+                keymap
+                    .get_or_insert_with(HashMap::new)
+                    .insert(keycode, (port, joypad_button()?));
+            }
+            Section::Hotkeys => {
+                let (key, shift) = match name.split_once('+') {
+                    Some((modifier, key)) if modifier.trim().eq_ignore_ascii_case("shift") => {
+                        (key.trim(), true)
+                    }
+                    _ => (name, false),
+                };
+                let keycode = Keycode::from_name(key)
+                    .ok_or_else(|| invalid(format!("unknown key '{}'", key)))?;
+                let action = Action::from_name(value)
+                    .ok_or_else(|| invalid(format!("unknown action '{}'", value)))?;
+                hotkeys
+                    .get_or_insert_with(HashMap::new)
+                    .insert((keycode, shift), action);
+            }
+            Section::Gamepad(_) if name.eq_ignore_ascii_case("deadzone") => {
+                deadzone = value
+                    .parse()
+                    .ok()
+                    .filter(|deadzone| *deadzone >= 0)
+                    .ok_or_else(|| invalid(format!("invalid deadzone '{}'", value)))?;
+            }
+            Section::Gamepad(port) => {
+                let button = Button::from_string(&name.to_lowercase())
+                    .ok_or_else(|| invalid(format!("unknown gamepad button '{}'", name)))?;
+                let joypad_button = joypad_button()?;
+                let ports = match port {
+                    Some(port) => port..=port,
+                    None => 0..=1,
+                };
+                for port in ports {
+                    buttons[port]
+                        .get_or_insert_with(HashMap::new)
+                        .insert(button, joypad_button);
+                }
+            }
+        }
+    }
+    Ok(Config {
+        keymap: keymap.unwrap_or_else(default_keymap),
+        hotkeys: hotkeys.unwrap_or_else(default_hotkeys),
+        buttons: buttons.map(|buttons| buttons.unwrap_or_else(default_buttons)),
+        deadzone,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_line(contents: &str) -> Option<usize> {
+        match parse_config(contents) {
+            Err(ConfigError::InvalidLine(line, _)) => Some(line),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn empty_config_keeps_defaults() {
+        let config = parse_config("# nothing here\n\n").unwrap();
+        assert_eq!(config.keymap, default_keymap());
+        assert_eq!(config.hotkeys, default_hotkeys());
+        assert_eq!(config.buttons, [default_buttons(), default_buttons()]);
+        assert_eq!(config.deadzone, DEFAULT_DEADZONE);
+    }
+
+    #[test]
+    fn parses_sections() {
+        let config = parse_config(
+            "\
+Z = b
+[player2]
+I = UP
+[hotkeys]
+Q = quit
+[gamepad2]
+x = start
+",
+        )
+        .unwrap();
+        // Keyboard bindings replace all of the default ones.
+        assert_eq!(config.keymap.len(), 2);
+        assert_eq!(config.keymap[&Keycode::Z], (0, JoypadButton::B));
+        assert_eq!(config.keymap[&Keycode::I], (1, JoypadButton::UP));
+        assert_eq!(config.hotkeys.len(), 1);
+        assert_eq!(config.hotkey(Keycode::Q, false), Some(Action::Quit));
+        // Only the second port's gamepad bindings were given.
+        assert_eq!(config.buttons[0], default_buttons());
+        assert_eq!(config.buttons[1].len(), 1);
+        assert_eq!(config.buttons[1][&Button::X], JoypadButton::START);
+    }
+
+    #[test]
+    fn gamepad_section_binds_both_ports() {
+        let config = parse_config("[gamepad]\na = A\n[gamepad1]\nb = B\n").unwrap();
+        assert_eq!(config.buttons[0].len(), 2);
+        assert_eq!(config.buttons[0][&Button::B], JoypadButton::B);
+        assert_eq!(config.buttons[1].len(), 1);
+        assert_eq!(config.buttons[1][&Button::A], JoypadButton::A);
+    }
+
+    #[test]
+    fn parses_shift_hotkeys() {
+        let config =
+            parse_config("[hotkeys]\nShift+F1 = load_state 1\nshift + F2 = reset\n").unwrap();
+        assert_eq!(config.hotkey(Keycode::F1, true), Some(Action::LoadState(1)));
+        assert_eq!(config.hotkey(Keycode::F1, false), None);
+        assert_eq!(config.hotkey(Keycode::F2, true), Some(Action::Reset));
+    }
+
+    #[test]
+    fn shifted_hotkey_falls_back_to_unshifted() {
+        let config = Config::default();
+        assert_eq!(config.hotkey(Keycode::F12, true), Some(Action::Reset));
+        assert_eq!(
+            config.hotkey(Keycode::F3, false),
+            Some(Action::SaveState(3))
+        );
+        assert_eq!(config.hotkey(Keycode::F3, true), Some(Action::LoadState(3)));
+    }
+
+    #[test]
+    fn validates_save_state_slots() {
+        let config = parse_config("[hotkeys]\nF1 = save_state 10\n").unwrap();
+        assert_eq!(
+            config.hotkey(Keycode::F1, false),
+            Some(Action::SaveState(10))
+        );
+        assert_eq!(invalid_line("[hotkeys]\nF1 = save_state 0\n"), Some(2));
+        assert_eq!(invalid_line("[hotkeys]\nF1 = save_state\n"), Some(2));
+        assert_eq!(invalid_line("[hotkeys]\nF1 = load_state x\n"), Some(2));
+        assert_eq!(invalid_line("[hotkeys]\nF1 = load_state 256\n"), Some(2));
+        assert_eq!(invalid_line("[hotkeys]\nF1 = quit 1\n"), Some(2));
+    }
+
+    #[test]
+    fn validates_deadzone() {
+        let config = parse_config("[gamepad2]\ndeadzone = 10000\n").unwrap();
+        assert_eq!(config.deadzone, 10000);
+        assert_eq!(invalid_line("[gamepad]\ndeadzone = -1\n"), Some(2));
+        assert_eq!(invalid_line("[gamepad]\ndeadzone = 40000\n"), Some(2));
+        assert_eq!(invalid_line("[gamepad]\ndeadzone = far\n"), Some(2));
+    }
+
+    #[test]
+    fn reports_invalid_lines() {
+        assert_eq!(invalid_line("[player3]\n"), Some(1));
+        assert_eq!(invalid_line("\n# comment\nZ B\n"), Some(3));
+        assert_eq!(invalid_line("NotAKey = A\n"), Some(1));
+        assert_eq!(invalid_line("Z = TURBO\n"), Some(1));
+        assert_eq!(invalid_line("[hotkeys]\nZ = explode\n"), Some(2));
+        assert_eq!(invalid_line("[gamepad]\nturbo = A\n"), Some(2));
+    }
+}
@@ -4,16 +4,17 @@ use bitflags::Flags;
 
 use crate::bus::Bus;
 use crate::opcodes;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 use std::collections::HashMap;
 
-pub struct CPU<'a> {
+pub struct CPU {
     pub reg_a: u8,
     pub reg_x: u8,
     pub reg_y: u8,
     pub reg_pc: u16,
     pub reg_sp: u8,
     pub reg_status: StatusFlags,
-    pub bus: Bus<'a>,
+    pub bus: Bus,
 }
 
 #[derive(Debug, PartialEq)]
@@ -52,7 +53,6 @@ bitflags! {
 pub trait Memory {
     fn mem_read(&mut self, addr: u16) -> u8;
     fn mem_write(&mut self, addr: u16, value: u8);
-    fn mem_write_u32(&mut self, addr: u32, value: u8);
     fn mem_read_u16(&mut self, addr: u16) -> u16 {
         // LL, HH are 6502 mnemonics
         let ll = self.mem_read(addr) as u16;
@@ -67,7 +67,7 @@ pub trait Memory {
     }
 }
 
-impl Memory for CPU<'_> {
+impl Memory for CPU {
     fn mem_read(&mut self, addr: u16) -> u8 {
         self.bus.mem_read(addr)
     }
@@ -80,8 +80,27 @@ impl Memory for CPU<'_> {
     fn mem_write_u16(&mut self, addr: u16, value: u16) {
         self.bus.mem_write_u16(addr, value)
     }
-    fn mem_write_u32(&mut self, addr: u32, value: u8) {
-        self.bus.mem_write_u32(addr, value)
+}
+
+impl Snapshot for CPU {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.reg_a);
+        state.write_u8(self.reg_x);
+        state.write_u8(self.reg_y);
+        state.write_u16(self.reg_pc);
+        state.write_u8(self.reg_sp);
+        state.write_u8(self.reg_status.bits());
+        self.bus.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.reg_a = state.read_u8()?;
+        self.reg_x = state.read_u8()?;
+        self.reg_y = state.read_u8()?;
+        self.reg_pc = state.read_u16()?;
+        self.reg_sp = state.read_u8()?;
+        self.reg_status = StatusFlags::from_bits_retain(state.read_u8()?);
+        self.bus.load_state(state)
     }
 }
 
@@ -89,8 +108,8 @@ fn page_crossed(a: u16, b: u16) -> bool {
     a & 0xFF00 != b & 0xFF00
 }
 
-impl<'a> CPU<'a> {
-    pub fn new<'b>(bus: Bus<'b>) -> CPU<'b> {
+impl CPU {
+    pub fn new(bus: Bus) -> CPU {
         CPU {
             reg_a: 0,
             reg_x: 0,
@@ -220,7 +239,7 @@ impl<'a> CPU<'a> {
     // 5  $0100,S  W  push P on stack (with B flag *clear*), decrement S
     // 6   A       R  fetch PCL (A = FFFE for IRQ, A = FFFA for NMI), set I flag
     // 7   A       R  fetch PCH (A = FFFF for IRQ, A = FFFB for NMI)
-    fn interrupt_nmi(&mut self) {
+    fn interrupt(&mut self, vector: u16) {
         self.stack_push_u16(self.reg_pc);
         let mut flags = self.reg_status.clone();
         flags.remove(StatusFlags::BREAK);
@@ -228,143 +247,173 @@ impl<'a> CPU<'a> {
         self.stack_push(flags.bits());
         self.reg_status.insert(StatusFlags::INTERRUPT_MASK);
         self.bus.tick(2);
-        self.reg_pc = self.mem_read_u16(0xFFFA);
+        self.reg_pc = self.mem_read_u16(vector);
+    }
+
+    fn interrupt_nmi(&mut self) {
+        self.interrupt(0xFFFA);
+    }
+
+    fn interrupt_irq(&mut self) {
+        self.interrupt(0xFFFE);
     }
 
     pub fn execute_with_callback<F>(&mut self, mut callback: F)
     where
         F: FnMut(&mut CPU),
     {
-        let ref jmp_table: HashMap<u8, &'static opcodes::Opcode> = *opcodes::OPCODES_JMP_TABLE;
         loop {
-            if let Some(nmi) = self.bus.poll_nmi_status() {
-                self.interrupt_nmi();
-            }
+            self.poll_interrupts();
             callback(self);
-            let opcode = self.mem_read(self.reg_pc);
-            self.reg_pc = self.reg_pc.wrapping_add(1);
-            let instruction = jmp_table.get(&opcode).unwrap();
-            let pc_snapshot = self.reg_pc;
-            match opcode {
-                0xA9 | 0xAD | 0xBD | 0xB9 | 0xA5 | 0xB5 | 0xA1 | 0xB1 => {
-                    self.lda(&instruction.addressing_mode)
-                }
-                0xA2 | 0xAE | 0xBE | 0xA6 | 0xB6 => self.ldx(&instruction.addressing_mode),
-                0xA0 | 0xAC | 0xBC | 0xA4 | 0xB4 => self.ldy(&instruction.addressing_mode),
-                0x8D | 0x9D | 0x99 | 0x85 | 0x95 | 0x81 | 0x91 => {
-                    self.sta(&instruction.addressing_mode)
-                }
-                0x8E | 0x86 | 0x96 => self.stx(&instruction.addressing_mode),
-                0x8C | 0x84 | 0x94 => self.sty(&instruction.addressing_mode),
-                0xAA => self.tax(),
-                0xA8 => self.tay(),
-                0xBA => self.tsx(),
-                0x8A => self.txa(),
-                0x9A => self.txs(),
-                0x98 => self.tya(),
-                0x48 => self.pha(),
-                0x08 => self.php(),
-                0x68 => self.pla(),
-                0x28 => self.plp(),
-                0x0A => self.asl_a(),
-                0x0E | 0x1E | 0x06 | 0x16 => self.asl(&instruction.addressing_mode),
-                0x4A => self.lsr_a(),
-                0x4E | 0x5E | 0x46 | 0x56 => self.lsr(&instruction.addressing_mode),
-                0x2A => self.rol_a(),
-                0x2E | 0x3E | 0x26 | 0x36 => self.rol(&instruction.addressing_mode),
-                0x6A => self.ror_a(),
-                0x6E | 0x7E | 0x66 | 0x76 => self.ror(&instruction.addressing_mode),
-                0x29 | 0x2D | 0x3D | 0x39 | 0x25 | 0x35 | 0x21 | 0x31 => {
-                    self.and(&instruction.addressing_mode)
-                }
-                0x2C | 0x24 => self.bit(&instruction.addressing_mode),
-                0x49 | 0x4D | 0x5D | 0x59 | 0x45 | 0x55 | 0x41 | 0x51 => {
-                    self.eor(&instruction.addressing_mode)
-                }
-                0x09 | 0x0D | 0x1D | 0x19 | 0x05 | 0x15 | 0x01 | 0x11 => {
-                    self.ora(&instruction.addressing_mode)
-                }
-                0x69 | 0x6D | 0x7D | 0x79 | 0x65 | 0x75 | 0x61 | 0x71 => {
-                    self.adc(&instruction.addressing_mode)
-                }
-                0xC9 | 0xCD | 0xDD | 0xD9 | 0xC5 | 0xD5 | 0xC1 | 0xD1 => {
-                    self.cmp(&instruction.addressing_mode, self.reg_a)
-                }
-                0xE0 | 0xEC | 0xE4 => self.cpx(&instruction.addressing_mode),
-                0xC0 | 0xCC | 0xC4 => self.cpy(&instruction.addressing_mode),
-                0xE9 | 0xED | 0xEb | 0xFD | 0xF9 | 0xE5 | 0xF5 | 0xE1 | 0xF1 => {
-                    self.sbc(&instruction.addressing_mode)
-                }
-                0xCE | 0xDE | 0xC6 | 0xD6 => self.dec(&instruction.addressing_mode),
-                0xCA => self.dex(),
-                0x88 => self.dey(),
-                0xEE | 0xFE | 0xE6 | 0xF6 => self.inc(&instruction.addressing_mode),
-                0xE8 => self.inx(),
-                0xC8 => self.iny(),
-                0x00 => {
-                    // return;
-                    self.brk();
-                    break;
-                }
-                0x4C => self.reg_pc = self.mem_read_u16(self.reg_pc), // JMP ABS
-                0x6C => self.jmp(),
-                0x20 => self.jsr(),
-                0x40 => self.rti(),
-                0x60 => self.rts(),
-                0x90 => self.bcc(),
-                0xB0 => self.bcs(),
-                0xF0 => self.beq(),
-                0x30 => self.bmi(),
-                0xD0 => self.bne(),
-                0x10 => self.bpl(),
-                0x50 => self.bvc(),
-                0x70 => self.bvs(),
-                0x18 => self.clc(),
-                0xD8 => self.cld(),
-                0x58 => self.cli(),
-                0xB8 => self.clv(),
-                0x38 => self.sec(),
-                0xF8 => self.sed(),
-                0x78 => self.sei(),
-                0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xEA | 0xFA | 0x80 | 0x82 | 0x89 | 0xC2
-                | 0xE2 | 0x0C | 0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC | 0x04 | 0x44 | 0x64
-                | 0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => self.nop(),
-                0xAB | 0xAF | 0xBF | 0xA7 | 0xB7 | 0xA3 | 0xB3 => {
-                    self.lax(&instruction.addressing_mode)
-                }
+            if !self.execute_instruction() {
+                return;
+            }
+        }
+    }
+
+    // Service any pending interrupt, then execute a single instruction.
+    // Returns false once execution reaches BRK or an unimplemented opcode.
+    pub fn step(&mut self) -> bool {
+        self.poll_interrupts();
+        self.execute_instruction()
+    }
+
+    fn poll_interrupts(&mut self) {
+        if let Some(nmi) = self.bus.poll_nmi_status() {
+            self.interrupt_nmi();
+        } else if self.bus.poll_irq_status()
+            && !self.reg_status.contains(StatusFlags::INTERRUPT_MASK)
+        {
+            self.interrupt_irq();
+        }
+    }
 
-                0x8F | 0x87 | 0x97 | 0x83 => self.sax(&instruction.addressing_mode),
-                0xCF | 0xDF | 0xDB | 0xC7 | 0xD7 | 0xC3 | 0xD3 => {
-                    self.dcp(&instruction.addressing_mode)
-                }
+    fn execute_instruction(&mut self) -> bool {
+        let ref jmp_table: HashMap<u8, &'static opcodes::Opcode> = *opcodes::OPCODES_JMP_TABLE;
+        let opcode = self.mem_read(self.reg_pc);
+        self.reg_pc = self.reg_pc.wrapping_add(1);
+        let instruction = jmp_table.get(&opcode).unwrap();
+        let pc_snapshot = self.reg_pc;
+        match opcode {
+            0xA9 | 0xAD | 0xBD | 0xB9 | 0xA5 | 0xB5 | 0xA1 | 0xB1 => {
+                self.lda(&instruction.addressing_mode)
+            }
+            0xA2 | 0xAE | 0xBE | 0xA6 | 0xB6 => self.ldx(&instruction.addressing_mode),
+            0xA0 | 0xAC | 0xBC | 0xA4 | 0xB4 => self.ldy(&instruction.addressing_mode),
+            0x8D | 0x9D | 0x99 | 0x85 | 0x95 | 0x81 | 0x91 => {
+                self.sta(&instruction.addressing_mode)
+            }
+            0x8E | 0x86 | 0x96 => self.stx(&instruction.addressing_mode),
+            0x8C | 0x84 | 0x94 => self.sty(&instruction.addressing_mode),
+            0xAA => self.tax(),
+            0xA8 => self.tay(),
+            0xBA => self.tsx(),
+            0x8A => self.txa(),
+            0x9A => self.txs(),
+            0x98 => self.tya(),
+            0x48 => self.pha(),
+            0x08 => self.php(),
+            0x68 => self.pla(),
+            0x28 => self.plp(),
+            0x0A => self.asl_a(),
+            0x0E | 0x1E | 0x06 | 0x16 => self.asl(&instruction.addressing_mode),
+            0x4A => self.lsr_a(),
+            0x4E | 0x5E | 0x46 | 0x56 => self.lsr(&instruction.addressing_mode),
+            0x2A => self.rol_a(),
+            0x2E | 0x3E | 0x26 | 0x36 => self.rol(&instruction.addressing_mode),
+            0x6A => self.ror_a(),
+            0x6E | 0x7E | 0x66 | 0x76 => self.ror(&instruction.addressing_mode),
+            0x29 | 0x2D | 0x3D | 0x39 | 0x25 | 0x35 | 0x21 | 0x31 => {
+                self.and(&instruction.addressing_mode)
+            }
+            0x2C | 0x24 => self.bit(&instruction.addressing_mode),
+            0x49 | 0x4D | 0x5D | 0x59 | 0x45 | 0x55 | 0x41 | 0x51 => {
+                self.eor(&instruction.addressing_mode)
+            }
+            0x09 | 0x0D | 0x1D | 0x19 | 0x05 | 0x15 | 0x01 | 0x11 => {
+                self.ora(&instruction.addressing_mode)
+            }
+            0x69 | 0x6D | 0x7D | 0x79 | 0x65 | 0x75 | 0x61 | 0x71 => {
+                self.adc(&instruction.addressing_mode)
+            }
+            0xC9 | 0xCD | 0xDD | 0xD9 | 0xC5 | 0xD5 | 0xC1 | 0xD1 => {
+                self.cmp(&instruction.addressing_mode, self.reg_a)
+            }
+            0xE0 | 0xEC | 0xE4 => self.cpx(&instruction.addressing_mode),
+            0xC0 | 0xCC | 0xC4 => self.cpy(&instruction.addressing_mode),
+            0xE9 | 0xED | 0xEb | 0xFD | 0xF9 | 0xE5 | 0xF5 | 0xE1 | 0xF1 => {
+                self.sbc(&instruction.addressing_mode)
+            }
+            0xCE | 0xDE | 0xC6 | 0xD6 => self.dec(&instruction.addressing_mode),
+            0xCA => self.dex(),
+            0x88 => self.dey(),
+            0xEE | 0xFE | 0xE6 | 0xF6 => self.inc(&instruction.addressing_mode),
+            0xE8 => self.inx(),
+            0xC8 => self.iny(),
+            0x00 => {
+                self.brk();
+                self.bus.tick(instruction.cycles);
+                return false;
+            }
+            0x4C => self.reg_pc = self.mem_read_u16(self.reg_pc), // JMP ABS
+            0x6C => self.jmp(),
+            0x20 => self.jsr(),
+            0x40 => self.rti(),
+            0x60 => self.rts(),
+            0x90 => self.bcc(),
+            0xB0 => self.bcs(),
+            0xF0 => self.beq(),
+            0x30 => self.bmi(),
+            0xD0 => self.bne(),
+            0x10 => self.bpl(),
+            0x50 => self.bvc(),
+            0x70 => self.bvs(),
+            0x18 => self.clc(),
+            0xD8 => self.cld(),
+            0x58 => self.cli(),
+            0xB8 => self.clv(),
+            0x38 => self.sec(),
+            0xF8 => self.sed(),
+            0x78 => self.sei(),
+            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xEA | 0xFA | 0x80 | 0x82 | 0x89 | 0xC2
+            | 0xE2 | 0x0C | 0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC | 0x04 | 0x44 | 0x64
+            | 0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => self.nop(),
+            0xAB | 0xAF | 0xBF | 0xA7 | 0xB7 | 0xA3 | 0xB3 => {
+                self.lax(&instruction.addressing_mode)
+            }
 
-                0xEF | 0xFF | 0xFB | 0xE7 | 0xF7 | 0xE3 | 0xF3 => {
-                    self.isc(&instruction.addressing_mode)
-                }
+            0x8F | 0x87 | 0x97 | 0x83 => self.sax(&instruction.addressing_mode),
+            0xCF | 0xDF | 0xDB | 0xC7 | 0xD7 | 0xC3 | 0xD3 => {
+                self.dcp(&instruction.addressing_mode)
+            }
 
-                0x0F | 0x1F | 0x1B | 0x07 | 0x17 | 0x03 | 0x13 => {
-                    self.slo(&instruction.addressing_mode)
-                }
+            0xEF | 0xFF | 0xFB | 0xE7 | 0xF7 | 0xE3 | 0xF3 => {
+                self.isc(&instruction.addressing_mode)
+            }
 
-                0x2F | 0x3F | 0x3B | 0x27 | 0x37 | 0x23 | 0x33 => {
-                    self.rla(&instruction.addressing_mode)
-                }
+            0x0F | 0x1F | 0x1B | 0x07 | 0x17 | 0x03 | 0x13 => {
+                self.slo(&instruction.addressing_mode)
+            }
 
-                0x4F | 0x5F | 0x5B | 0x47 | 0x57 | 0x43 | 0x53 => {
-                    self.sre(&instruction.addressing_mode)
-                }
-                0x6F | 0x7F | 0x7B | 0x67 | 0x77 | 0x63 | 0x73 => {
-                    self.rra(&instruction.addressing_mode)
-                }
-                _ => {
-                    return;
-                }
+            0x2F | 0x3F | 0x3B | 0x27 | 0x37 | 0x23 | 0x33 => {
+                self.rla(&instruction.addressing_mode)
             }
-            self.bus.tick(instruction.cycles);
-            if pc_snapshot == self.reg_pc {
-                self.reg_pc += (&instruction.length - 1) as u16;
+
+            0x4F | 0x5F | 0x5B | 0x47 | 0x57 | 0x43 | 0x53 => {
+                self.sre(&instruction.addressing_mode)
+            }
+            0x6F | 0x7F | 0x7B | 0x67 | 0x77 | 0x63 | 0x73 => {
+                self.rra(&instruction.addressing_mode)
             }
+            _ => {
+                return false;
+            }
+        }
+        self.bus.tick(instruction.cycles);
+        if pc_snapshot == self.reg_pc {
+            self.reg_pc += (&instruction.length - 1) as u16;
         }
+        true
     }
 
     fn handle_flags_z_n(&mut self, value: u8) {
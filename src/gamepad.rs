@@ -0,0 +1,79 @@
+use crate::config::Config;
+use nesemu::joypad::JoypadButton;
+use sdl2::controller::{Axis, GameController};
+use sdl2::GameControllerSubsystem;
+
+// Game controllers which are plugged in, each one playing in the first
+// port that was free when it was connected.
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    ports: [Option<GameController>; 2],
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Gamepads {
+            subsystem,
+            ports: [None, None],
+        }
+    }
+
+    // SDL also reports the controllers already plugged in at startup as
+    // being added, so this covers both those and hot-plugged ones. The
+    // index is the device index, not an instance id.
+    pub fn connect(&mut self, index: u32) {
+        let port = match self.ports.iter().position(Option::is_none) {
+            Some(port) => port,
+            None => {
+                println!("Ignoring controller {}, both ports are in use", index);
+                return;
+            }
+        };
+        match self.subsystem.open(index) {
+            Ok(controller) => {
+                println!("{} connected to port {}", controller.name(), port + 1);
+                self.ports[port] = Some(controller);
+            }
+            Err(e) => println!("Unable to open controller {}: {}", index, e),
+        }
+    }
+
+    pub fn disconnect(&mut self, instance_id: u32) {
+        for port in self.ports.iter_mut() {
+            if port.as_ref().map(GameController::instance_id) == Some(instance_id) {
+                if let Some(controller) = port.take() {
+                    println!("{} disconnected", controller.name());
+                }
+            }
+        }
+    }
+
+    // Buttons held on the controller in a port, with the left stick
+    // standing in for the d-pad once it's pushed past the deadzone.
+    pub fn buttons(&self, port: usize, config: &Config) -> JoypadButton {
+        let mut buttons = JoypadButton::empty();
+        let controller = match &self.ports[port] {
+            Some(controller) => controller,
+            None => return buttons,
+        };
+        for (button, joypad_button) in config.buttons[port].iter() {
+            if controller.button(*button) {
+                buttons.insert(*joypad_button);
+            }
+        }
+        let x = controller.axis(Axis::LeftX);
+        let y = controller.axis(Axis::LeftY);
+        let directions = [
+            (x < -config.deadzone, JoypadButton::LEFT),
+            (x > config.deadzone, JoypadButton::RIGHT),
+            (y < -config.deadzone, JoypadButton::UP),
+            (y > config.deadzone, JoypadButton::DOWN),
+        ];
+        for (pushed, direction) in directions {
+            if pushed {
+                buttons.insert(direction);
+            }
+        }
+        buttons
+    }
+}
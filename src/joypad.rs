@@ -1,7 +1,7 @@
-use bitflags::Flags;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 bitflags! {
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, PartialEq, Debug)]
     pub struct JoypadButton: u8 {
         const RIGHT = 0b10000000;
         const LEFT = 0b01000000;
@@ -51,4 +51,30 @@ impl Joypad {
     pub fn set_pressed(&mut self, button: JoypadButton, pressed: bool) {
         self.button_status.set(button, pressed);
     }
+
+    // Replace the state of every button at once.
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.button_status = buttons;
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.strobe_status);
+        state.write_u8(self.button_status.bits());
+        state.write_u8(self.button_index);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.strobe_status = state.read_bool()?;
+        self.button_status = JoypadButton::from_bits_retain(state.read_u8()?);
+        self.button_index = state.read_u8()?;
+        Ok(())
+    }
 }
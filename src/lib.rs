@@ -0,0 +1,21 @@
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod joypad;
+pub mod mapper;
+pub mod movie;
+pub mod nes;
+mod opcodes;
+pub mod pacer;
+pub mod ppu;
+pub mod rewind;
+pub mod state;
+
+pub use nes::Nes;
+
+#[macro_use]
+extern crate lazy_static;
+
+#[macro_use]
+extern crate bitflags;
@@ -1,33 +1,46 @@
-#![allow(warnings)]
-mod bus;
-mod cartridge;
-mod cpu;
-mod joypad;
-mod opcodes;
-mod ppu;
-mod mapper;
-
-use bus::Bus;
-use cartridge::MirroringType;
-use cartridge::Cartridge;
-use cpu::Memory;
-use cpu::CPU;
-use ppu::frame::Frame;
-use ppu::PPU;
-use rand::Rng;
+mod cli;
+mod config;
+mod gamepad;
+
+use cli::Options;
+use config::{Action, Config};
+use gamepad::Gamepads;
+use nesemu::apu;
+use nesemu::cartridge::Cartridge;
+use nesemu::cartridge::SaveFile;
+use nesemu::cpu::Memory;
+use nesemu::cpu::CPU;
+use nesemu::joypad::JoypadButton;
+use nesemu::movie::{Movie, MovieFrame};
+use nesemu::pacer;
+use nesemu::pacer::FramePacer;
+use nesemu::rewind::RewindBuffer;
+use nesemu::ppu::frame::FrameBuffer;
+use nesemu::ppu::frame::DEFAULT_PALETTE;
+use nesemu::Nes;
+use sdl2::audio::AudioQueue;
+use sdl2::audio::AudioSpecDesired;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::keyboard::Mod;
 use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
+use sdl2::video::FullscreenType;
 use sdl2::EventPump;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-#[macro_use]
-extern crate lazy_static;
-
-#[macro_use]
-extern crate bitflags;
+// Flush battery backed RAM to disk roughly every five seconds.
+const SAVE_INTERVAL_FRAMES: usize = 300;
+// Stop queueing audio once this many samples are waiting, so latency can't
+// build up when emulation runs ahead of playback.
+const MAX_QUEUED_SAMPLES: u32 = apu::SAMPLE_RATE / 10;
+// Depth the frame pacer steers the audio queue towards.
+const TARGET_QUEUED_SAMPLES: u32 = MAX_QUEUED_SAMPLES / 2;
+// How much gameplay can be rewound.
+const REWIND_SECONDS: f64 = 30.0;
 
+#[allow(dead_code)]
 fn color(byte: u8) -> Color {
     match byte {
         0 => sdl2::pixels::Color::BLACK,
@@ -42,6 +55,7 @@ fn color(byte: u8) -> Color {
     }
 }
 
+#[allow(dead_code)]
 fn read_screen_state(cpu: &mut CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     let mut frame_idx = 0;
     let mut update = false;
@@ -59,6 +73,7 @@ fn read_screen_state(cpu: &mut CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     update
 }
 
+#[allow(dead_code)]
 fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
     for event in event_pump.poll_iter() {
         match event {
@@ -96,133 +111,408 @@ fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
     }
 }
 
+fn flush_save_file(save_file: &mut Option<SaveFile>) {
+    if let Some(save) = save_file.as_mut() {
+        if let Err(e) = save.flush() {
+            println!("Unable to write save file: {}", e);
+        }
+    }
+}
+
+// Save state slots live next to the ROM, e.g. game.ss1 - game.ss10.
+fn state_path(rom_path: &Path, slot: u8) -> PathBuf {
+    rom_path.with_extension(format!("ss{}", slot))
+}
+
+fn shift_held(keymod: Mod) -> bool {
+    keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD)
+}
+
+// Returns whether the state was saved.
+fn save_state(nes: &Nes, rom_path: &Path, slot: u8) -> bool {
+    let path = state_path(rom_path, slot);
+    match std::fs::write(&path, nes.save_state()) {
+        Ok(()) => {
+            println!("Saved state to slot {}", slot);
+            true
+        }
+        Err(e) => {
+            println!("Unable to write save state {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+// Load a state from a slot, returning the state that was loaded.
+fn read_state(nes: &mut Nes, rom_path: &Path, slot: u8) -> Result<Vec<u8>, String> {
+    let data = std::fs::read(state_path(rom_path, slot)).map_err(|e| e.to_string())?;
+    nes.load_state(&data).map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+// Returns whether the state was loaded.
+fn load_state(nes: &mut Nes, rom_path: &Path, slot: u8) -> bool {
+    match read_state(nes, rom_path, slot) {
+        Ok(_) => {
+            println!("Loaded state from slot {}", slot);
+            true
+        }
+        Err(e) => {
+            let path = state_path(rom_path, slot);
+            println!("Unable to load save state {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+fn load_movie(path: &Path) -> Result<Movie, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("unable to read movie '{}': {}", path.display(), e))?;
+    Movie::from_fm2(&contents)
+        .map_err(|e| format!("unable to load movie '{}': {}", path.display(), e))
+}
+
+fn save_movie(movie: &Option<Movie>, path: &Option<PathBuf>) {
+    if let (Some(movie), Some(path)) = (movie, path) {
+        if let Err(e) = std::fs::write(path, movie.to_fm2()) {
+            println!("Unable to write movie {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Input for the next frame, from the movie being played back while it
+// lasts and from the keyboard after that.
+fn frame_input(playback: &Option<Movie>, frame: usize, buttons: [JoypadButton; 2]) -> MovieFrame {
+    playback
+        .as_ref()
+        .and_then(|movie| movie.frame(frame))
+        .unwrap_or(MovieFrame::new(buttons))
+}
+
+fn load_rom(path: &Path) -> Result<Cartridge, String> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| format!("unable to read ROM '{}': {}", path.display(), e))?;
+    Cartridge::new(&bytes).map_err(|e| format!("unable to load ROM '{}': {}", path.display(), e))
+}
+
 fn main() {
-    let palette: [(u8, u8, u8); 64] = [
-        (0x80, 0x80, 0x80),
-        (0x00, 0x3D, 0xA6),
-        (0x00, 0x12, 0xB0),
-        (0x44, 0x00, 0x96),
-        (0xA1, 0x00, 0x5E),
-        (0xC7, 0x00, 0x28),
-        (0xBA, 0x06, 0x00),
-        (0x8C, 0x17, 0x00),
-        (0x5C, 0x2F, 0x00),
-        (0x10, 0x45, 0x00),
-        (0x05, 0x4A, 0x00),
-        (0x00, 0x47, 0x2E),
-        (0x00, 0x41, 0x66),
-        (0x00, 0x00, 0x00),
-        (0x05, 0x05, 0x05),
-        (0x05, 0x05, 0x05),
-        (0xC7, 0xC7, 0xC7),
-        (0x00, 0x77, 0xFF),
-        (0x21, 0x55, 0xFF),
-        (0x82, 0x37, 0xFA),
-        (0xEB, 0x2F, 0xB5),
-        (0xFF, 0x29, 0x50),
-        (0xFF, 0x22, 0x00),
-        (0xD6, 0x32, 0x00),
-        (0xC4, 0x62, 0x00),
-        (0x35, 0x80, 0x00),
-        (0x05, 0x8F, 0x00),
-        (0x00, 0x8A, 0x55),
-        (0x00, 0x99, 0xCC),
-        (0x21, 0x21, 0x21),
-        (0x09, 0x09, 0x09),
-        (0x09, 0x09, 0x09),
-        (0xFF, 0xFF, 0xFF),
-        (0x0F, 0xD7, 0xFF),
-        (0x69, 0xA2, 0xFF),
-        (0xD4, 0x80, 0xFF),
-        (0xFF, 0x45, 0xF3),
-        (0xFF, 0x61, 0x8B),
-        (0xFF, 0x88, 0x33),
-        (0xFF, 0x9C, 0x12),
-        (0xFA, 0xBC, 0x20),
-        (0x9F, 0xE3, 0x0E),
-        (0x2B, 0xF0, 0x35),
-        (0x0C, 0xF0, 0xA4),
-        (0x05, 0xFB, 0xFF),
-        (0x5E, 0x5E, 0x5E),
-        (0x0D, 0x0D, 0x0D),
-        (0x0D, 0x0D, 0x0D),
-        (0xFF, 0xFF, 0xFF),
-        (0xA6, 0xFC, 0xFF),
-        (0xB3, 0xEC, 0xFF),
-        (0xDA, 0xAB, 0xEB),
-        (0xFF, 0xA8, 0xF9),
-        (0xFF, 0xAB, 0xB3),
-        (0xFF, 0xD2, 0xB0),
-        (0xFF, 0xEF, 0xA6),
-        (0xFF, 0xF7, 0x9C),
-        (0xD7, 0xE8, 0x95),
-        (0xA6, 0xED, 0xAF),
-        (0xA2, 0xF2, 0xDA),
-        (0x99, 0xFF, 0xFC),
-        (0xDD, 0xDD, 0xDD),
-        (0x11, 0x11, 0x11),
-        (0x11, 0x11, 0x11),
-    ];
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("NESemu", (256.0 * 3.0) as u32, (240.0 * 3.0) as u32)
-        .position_centered()
-        .build()
-        .unwrap();
-
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(3.0, 3.0).unwrap();
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) if options.help => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("nesemu: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(options) {
+        eprintln!("nesemu: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let palette = match &options.palette {
+        Some(path) => FrameBuffer::read_palette_from_file(path)
+            .map_err(|e| format!("unable to load palette '{}': {}", path.display(), e))?,
+        None => DEFAULT_PALETTE.to_vec(),
+    };
+    let config = match &options.config {
+        Some(path) => config::load_config(path)
+            .map_err(|e| format!("unable to load config '{}': {}", path.display(), e))?,
+        None => Config::default(),
+    };
+
+    let rom = load_rom(&options.rom)?;
+    // Movies start from power on or a save state, neither of which has the
+    // .sav loaded, so leave it alone while playing or recording one.
+    let movie = options.play.is_some() || options.record.is_some();
+    let mut save_file = match rom.header.battery && !movie {
+        true => Some(SaveFile::new(&options.rom, &rom)),
+        false => None,
+    };
+    if let Some(save) = save_file.as_mut() {
+        if let Err(e) = save.load() {
+            println!("Unable to load save file: {}", e);
+        }
+    }
+
+    // When running at another region's refresh rate the CPU is effectively
+    // clocked faster or slower, which the audio has to follow.
+    let frame_rate = pacer::frame_rate(options.region.unwrap_or(rom.header.timing));
+    let clock_rate = (apu::CPU_CLOCK_RATE as f64 * frame_rate / pacer::NTSC_FRAME_RATE) as u32;
+    let mut nes = Nes::new(rom);
+    nes.set_palette(palette);
+    nes.set_sprite_limit(!options.no_sprite_limit);
+    let frame_limit = options.frames;
+    let mut frame_count: usize = 0;
+
+    let playback = match &options.play {
+        Some(path) => Some(load_movie(path)?),
+        None => None,
+    };
+    if let Some(state) = playback.as_ref().and_then(|movie| movie.start_state.as_ref()) {
+        nes.load_state(state)
+            .map_err(|e| format!("unable to start movie: {}", e))?;
+    }
+    let rom_name = options.rom.file_stem().unwrap_or_default().to_string_lossy();
+    // The input frame each slot was saved at during this recording, so that
+    // loading one can cut the movie back to that point.
+    let mut recorded_slots: HashMap<u8, usize> = HashMap::new();
+    let start_state = match options.record_from {
+        Some(slot) => {
+            let state = read_state(&mut nes, &options.rom, slot).map_err(|e| {
+                format!("unable to start recording from slot {}: {}", slot, e)
+            })?;
+            recorded_slots.insert(slot, 0);
+            Some(state)
+        }
+        None => None,
+    };
+    let mut recording = options
+        .record
+        .as_ref()
+        .map(|_| Movie::new(&rom_name, start_state));
+    // Frames of input so far, which goes back down when rewinding.
+    let mut input_frame: usize = 0;
+
+    if options.headless {
+        while frame_limit != Some(frame_count) {
+            let input = frame_input(&playback, input_frame, [JoypadButton::empty(); 2]);
+            nes.step_movie_frame(&input);
+            if let Some(movie) = recording.as_mut() {
+                movie.record(input);
+            }
+            input_frame += 1;
+            if nes.halted() {
+                println!("CPU halted after {} frames", frame_count + 1);
+                break;
+            }
+            nes.take_audio_samples();
+            frame_count += 1;
+            if frame_count.is_multiple_of(SAVE_INTERVAL_FRAMES) {
+                flush_save_file(&mut save_file);
+            }
+        }
+        flush_save_file(&mut save_file);
+        save_movie(&recording, &options.record);
+        return Ok(());
+    }
+
+    let scale = options.scale;
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let mut window_builder = video_subsystem.window("NESemu", 256 * scale, 240 * scale);
+    window_builder.position_centered();
+    if options.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().map_err(|e| e.to_string())?;
+
+    let audio_queue: Option<AudioQueue<f32>> = match options.mute {
+        true => None,
+        false => {
+            let audio_subsystem = sdl_context.audio()?;
+            let audio_spec = AudioSpecDesired {
+                freq: Some(apu::SAMPLE_RATE as i32),
+                channels: Some(1),
+                samples: Some(1024),
+            };
+            let audio_queue = audio_subsystem.open_queue(None, &audio_spec)?;
+            audio_queue.resume();
+            Some(audio_queue)
+        }
+    };
+    let sample_rate = audio_queue
+        .as_ref()
+        .map_or(apu::SAMPLE_RATE, |queue| queue.spec().freq as u32);
+
+    let mut gamepads = Gamepads::new(sdl_context.game_controller()?);
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let mut event_pump = sdl_context.event_pump()?;
+    // Keep the picture at the right aspect ratio when fullscreen.
+    canvas
+        .set_logical_size(256, 240)
+        .map_err(|e| e.to_string())?;
 
     let creator = canvas.texture_creator();
     let mut texture = creator
         .create_texture_target(PixelFormatEnum::RGB24, 256, 240)
-        .unwrap();
-
-    let bytes: Vec<u8> = std::fs::read("roms/contra.nes").unwrap();
-    let rom = Cartridge::new(&bytes).unwrap();
-
-    let mut frame = Frame::new();
-
-    let mut keymap = HashMap::new();
-    keymap.insert(Keycode::Down, joypad::JoypadButton::DOWN);
-    keymap.insert(Keycode::Up, joypad::JoypadButton::UP);
-    keymap.insert(Keycode::Right, joypad::JoypadButton::RIGHT);
-    keymap.insert(Keycode::Left, joypad::JoypadButton::LEFT);
-    keymap.insert(Keycode::Space, joypad::JoypadButton::SELECT);
-    keymap.insert(Keycode::Return, joypad::JoypadButton::START);
-    keymap.insert(Keycode::A, joypad::JoypadButton::A);
-    keymap.insert(Keycode::S, joypad::JoypadButton::B);
-
-    let bus = Bus::new(rom, move |ppu: &PPU, joypad: &mut joypad::Joypad| {
-        // let palette = Frame::read_palette_from_file("palettes/nes.hex");
-        Frame::render(ppu, &mut frame, palette.to_vec());
-        texture.update(None, &frame.frame_data, 256 * 3).unwrap();
-        canvas.copy(&texture, None, None).unwrap();
+        .map_err(|e| e.to_string())?;
+
+    let mut pacer = FramePacer::new(frame_rate);
+
+    nes.set_audio_output(clock_rate, sample_rate);
+    let mut buttons = [JoypadButton::empty(); 2];
+    // A state is kept for every frame so rewinding plays back frame by frame.
+    let mut rewind = RewindBuffer::new((REWIND_SECONDS * frame_rate) as usize);
+    // The key held to rewind, which stops it when released whatever
+    // modifiers are held by then.
+    let mut rewind_key: Option<Keycode> = None;
+    let mut paused = false;
+    // Pressed between frames, so the reset lands in the recorded input.
+    let mut reset_pending = false;
+
+    'running: loop {
+        let frame = match rewind_key.is_some() {
+            true => {
+                if let Some(state) = rewind.pop() {
+                    match nes.load_state(state) {
+                        Ok(()) => input_frame = input_frame.saturating_sub(1),
+                        Err(e) => {
+                            println!("Unable to rewind: {}", e);
+                            rewind.clear();
+                        }
+                    }
+                }
+                if let Some(movie) = recording.as_mut() {
+                    movie.truncate(input_frame);
+                }
+                // States don't hold the picture, so run the next frame to
+                // show where we've rewound to, then go back again.
+                let state = nes.save_state();
+                nes.step_frame();
+                nes.take_audio_samples();
+                nes.load_state(&state)
+                    .expect("restoring a state that was just saved");
+                nes.frame()
+            }
+            false if paused => nes.frame(),
+            false => {
+                let held = [
+                    buttons[0] | gamepads.buttons(0, &config),
+                    buttons[1] | gamepads.buttons(1, &config),
+                ];
+                let mut input = frame_input(&playback, input_frame, held);
+                if reset_pending {
+                    input.press_reset();
+                    reset_pending = false;
+                }
+                if playback.as_ref().map(Movie::len) == Some(input_frame) {
+                    println!("Movie finished");
+                }
+                let running = !nes.halted();
+                nes.step_movie_frame(&input);
+                if running && nes.halted() {
+                    println!("CPU halted, reset or load a state to continue");
+                }
+                if let Some(movie) = recording.as_mut() {
+                    movie.record(input);
+                }
+                input_frame += 1;
+                rewind.push(nes.save_state());
+                nes.frame()
+            }
+        };
+        texture
+            .update(None, &frame.frame_data, 256 * 3)
+            .map_err(|e| e.to_string())?;
+        canvas.copy(&texture, None, None)?;
         canvas.present();
+        let samples = nes.take_audio_samples();
+        let audio_fill = audio_queue.as_ref().map(|audio_queue| {
+            let queued_samples = audio_queue.size() / std::mem::size_of::<f32>() as u32;
+            if queued_samples < MAX_QUEUED_SAMPLES {
+                audio_queue.queue_audio(&samples).unwrap();
+            }
+            queued_samples as f64 / TARGET_QUEUED_SAMPLES as f64
+        });
+        pacer.wait(audio_fill);
+        frame_count += 1;
+        if frame_limit == Some(frame_count) {
+            break;
+        }
+        if frame_count.is_multiple_of(SAVE_INTERVAL_FRAMES) {
+            flush_save_file(&mut save_file);
+        }
         for event in event_pump.poll_iter() {
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
+                Event::Quit { .. } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat: false,
                     ..
-                } => std::process::exit(0),
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = keymap.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        joypad.set_pressed(*key, true);
+                } if config.hotkey(keycode, shift_held(keymod)).is_some() => {
+                    match config.hotkey(keycode, shift_held(keymod)).unwrap() {
+                        Action::Quit => break 'running,
+                        Action::Rewind => {
+                            rewind_key = Some(keycode);
+                            // Rewinding while recording rerecords the rewound frames.
+                            if let Some(movie) = recording.as_mut() {
+                                movie.rerecord_count += 1;
+                            }
+                        }
+                        Action::Pause => paused = !paused,
+                        Action::Reset => reset_pending = true,
+                        Action::ToggleFullscreen => {
+                            let window = canvas.window_mut();
+                            let fullscreen = match window.fullscreen_state() {
+                                FullscreenType::Off => FullscreenType::Desktop,
+                                _ => FullscreenType::Off,
+                            };
+                            if let Err(e) = window.set_fullscreen(fullscreen) {
+                                println!("Unable to toggle fullscreen: {}", e);
+                            }
+                        }
+                        Action::SaveState(slot) => {
+                            if save_state(&nes, &options.rom, slot) && recording.is_some() {
+                                recorded_slots.insert(slot, input_frame);
+                            }
+                        }
+                        Action::LoadState(slot) => match recording.as_mut() {
+                            None => {
+                                load_state(&mut nes, &options.rom, slot);
+                            }
+                            // Loading while recording rerecords everything after
+                            // the state, which has to be one saved in this movie.
+                            Some(movie) => match recorded_slots.get(&slot) {
+                                Some(&frame) => {
+                                    if load_state(&mut nes, &options.rom, slot) {
+                                        input_frame = frame;
+                                        movie.truncate(frame);
+                                        movie.rerecord_count += 1;
+                                        rewind.clear();
+                                    }
+                                }
+                                None => println!(
+                                    "Slot {} wasn't saved during this recording, not loading it",
+                                    slot
+                                ),
+                            },
+                        },
                     }
                 }
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = keymap.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        joypad.set_pressed(*key, false);
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } if rewind_key == Some(keycode) => rewind_key = None,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some((port, key)) = config.keymap.get(&keycode) {
+                        buttons[*port].set(*key, true);
                     }
                 }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some((port, key)) = config.keymap.get(&keycode) {
+                        buttons[*port].set(*key, false);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => gamepads.connect(which),
+                Event::ControllerDeviceRemoved { which, .. } => gamepads.disconnect(which),
                 _ => {}
             }
         }
-    });
-    let mut cpu = CPU::new(bus);
-    cpu.reset();
-    cpu.run();
+    }
+    flush_save_file(&mut save_file);
+    save_movie(&recording, &options.record);
+    Ok(())
 }
@@ -1,8 +1,57 @@
+pub mod mmc1;
+pub mod mmc3;
 pub mod nrom;
 pub mod uxrom;
 
-pub trait Mapper {
-    fn map_prg(&self, address: u16) -> u32;
-    fn map_chr(&self, address: u16) -> u32;
-    fn bank_select(&mut self, value: u8);
+use crate::cartridge::MirroringType;
+use crate::state::Snapshot;
+
+/*
+ *   A mapper owns the cartridge memory (PRG ROM, PRG RAM and CHR) along with
+ *   any registers used to bank it. The CPU sees the mapper at 0x4020 - 0xFFFF
+ *   and the PPU sees it at 0x0000 - 0x1FFF.
+ *
+ *   Save states hold the mapper's registers and any RAM, but not its ROM.
+ */
+pub trait Mapper: Snapshot {
+    fn cpu_read(&self, address: u16) -> u8;
+    fn cpu_write(&mut self, address: u16, value: u8);
+    fn ppu_read(&self, address: u16) -> u8;
+    fn ppu_write(&mut self, address: u16, value: u8);
+    fn mirroring(&self) -> MirroringType;
+    // Called with each pattern table address the PPU puts on its bus, so
+    // mappers can watch address lines (MMC3 clocks its IRQ counter off A12).
+    fn ppu_bus_address(&mut self, _address: u16) {}
+    fn irq_pending(&self) -> bool {
+        false
+    }
+    // PRG RAM mapped at 0x6000 - 0x7FFF, empty for boards without any.
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+}
+
+// Number of `bank_size` banks in `length` bytes of ROM. Bank numbers wrap
+// around the count, so it's never zero, even for ROM smaller than a bank.
+fn bank_count(length: usize, bank_size: usize) -> usize {
+    (length / bank_size).max(1)
+}
+
+// PRG RAM smaller than 8KB is mirrored through 0x6000 - 0x7FFF. Boards
+// without any read back 0 and ignore writes.
+fn read_prg_ram(prg_ram: &[u8], address: u16) -> u8 {
+    match prg_ram.len() {
+        0 => 0,
+        length => prg_ram[(address - 0x6000) as usize % length],
+    }
+}
+
+fn write_prg_ram(prg_ram: &mut [u8], address: u16, value: u8) {
+    if !prg_ram.is_empty() {
+        let length = prg_ram.len();
+        prg_ram[(address - 0x6000) as usize % length] = value;
+    }
 }
@@ -0,0 +1,291 @@
+use super::{bank_count, read_prg_ram, write_prg_ram, Mapper};
+use crate::cartridge::{ChrMemory, MirroringType};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// The shift register is reset to this value so that the marker bit reaches
+// bit 0 on the fifth write, signalling a full 5-bit value has been loaded.
+const SHIFT_REGISTER_RESET: u8 = 0b10000;
+
+pub struct MMC1 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    prg_ram_enabled: bool,
+    shift_register: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+    prg_banks: usize,
+    chr_banks: usize,
+}
+
+impl MMC1 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize) -> Self {
+        let prg_banks = bank_count(prg_rom.len(), 0x4000);
+        let chr_banks = bank_count(chr.len(), 0x1000);
+        MMC1 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            prg_ram_enabled: true,
+            shift_register: SHIFT_REGISTER_RESET,
+            // Power on with the last PRG bank fixed at 0xC000.
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            prg_banks,
+            chr_banks,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => {
+                // Bit 4 of the PRG bank register disables PRG RAM.
+                self.prg_bank = value & 0x0F;
+                self.prg_ram_enabled = value & 0x10 == 0;
+            }
+        }
+    }
+
+    /*
+     *   Control register (0x8000 - 0x9FFF)
+     *
+     *   4bit0
+     *   -----
+     *   CPPMM
+     *   |||++- Mirroring (0: one-screen, lower bank; 1: one-screen, upper bank;
+     *   |||               2: vertical; 3: horizontal)
+     *   |++--- PRG ROM bank mode (0, 1: switch 32 KB at 0x8000, ignoring low bit of bank number;
+     *   |                         2: fix first bank at 0x8000 and switch 16 KB bank at 0xC000;
+     *   |                         3: fix last bank at 0xC000 and switch 16 KB bank at 0x8000)
+     *   +----- CHR ROM bank mode (0: switch 8 KB at a time; 1: switch two separate 4 KB banks)
+     */
+
+    fn map_prg(&self, address: u16) -> u32 {
+        let prg_bank = self.prg_bank as usize;
+        let bank = match ((self.control >> 2) & 0b11, address) {
+            (0 | 1, 0x8000..=0xBFFF) => prg_bank & 0x0E,
+            (0 | 1, _) => (prg_bank & 0x0E) | 1,
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => prg_bank,
+            (_, 0x8000..=0xBFFF) => prg_bank,
+            (_, _) => self.prg_banks - 1,
+        };
+        let bank = bank % self.prg_banks;
+        (0x4000 * bank + (address & 0x3FFF) as usize) as u32
+    }
+
+    fn map_chr(&self, address: u16) -> u32 {
+        let bank = match ((self.control >> 4) & 0b1, address) {
+            (0, 0x0000..=0x0FFF) => self.chr_bank_0 & 0x1E,
+            (0, _) => (self.chr_bank_0 & 0x1E) | 1,
+            (_, 0x0000..=0x0FFF) => self.chr_bank_0,
+            (_, _) => self.chr_bank_1,
+        } as usize;
+        let bank = bank % self.chr_banks;
+        (0x1000 * bank + (address & 0x0FFF) as usize) as u32
+    }
+}
+
+impl Mapper for MMC1 {
+    fn cpu_read(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled => read_prg_ram(&self.prg_ram, address),
+            0x8000..=0xFFFF => self.prg_rom[self.map_prg(address) as usize],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            if address >= 0x6000 && self.prg_ram_enabled {
+                write_prg_ram(&mut self.prg_ram, address, value);
+            }
+            return;
+        }
+        // Writing a value with bit 7 set clears the shift register and
+        // locks the PRG bank mode back to 3.
+        if value & 0x80 != 0 {
+            self.shift_register = SHIFT_REGISTER_RESET;
+            self.control |= 0x0C;
+            return;
+        }
+        let complete = self.shift_register & 0b1 == 1;
+        self.shift_register = (self.shift_register >> 1) | ((value & 0b1) << 4);
+        if complete {
+            // Only the address of the fifth write selects the register.
+            self.write_register(address, self.shift_register);
+            self.shift_register = SHIFT_REGISTER_RESET;
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(self.map_chr(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.map_chr(address), value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        match self.control & 0b11 {
+            0 => MirroringType::SingleScreenLower,
+            1 => MirroringType::SingleScreenUpper,
+            2 => MirroringType::Vertical,
+            _ => MirroringType::Horizontal,
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for MMC1 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_vec(&self.prg_ram);
+        state.write_bool(self.prg_ram_enabled);
+        state.write_u8(self.shift_register);
+        state.write_u8(self.control);
+        state.write_u8(self.chr_bank_0);
+        state.write_u8(self.chr_bank_1);
+        state.write_u8(self.prg_bank);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_vec(&mut self.prg_ram)?;
+        self.prg_ram_enabled = state.read_bool()?;
+        self.shift_register = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.chr_bank_0 = state.read_u8()?;
+        self.chr_bank_1 = state.read_u8()?;
+        self.prg_bank = state.read_u8()?;
+        self.chr.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 8 PRG banks and 8 CHR banks, each filled with its own bank number.
+    fn test_mmc1() -> MMC1 {
+        let prg_rom = (0..8).flat_map(|bank| vec![bank; 0x4000]).collect();
+        let chr_rom = (0..8).flat_map(|bank| vec![bank; 0x1000]).collect();
+        MMC1::new(prg_rom, ChrMemory::rom(chr_rom), 0x2000)
+    }
+
+    // Load a register through the shift register, low bit first.
+    fn write_register(mmc1: &mut MMC1, address: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(address, (value >> bit) & 0b1);
+        }
+    }
+
+    fn prg_banks(mmc1: &MMC1) -> (u8, u8) {
+        (mmc1.cpu_read(0x8000), mmc1.cpu_read(0xC000))
+    }
+
+    fn chr_banks(mmc1: &MMC1) -> (u8, u8) {
+        (mmc1.ppu_read(0x0000), mmc1.ppu_read(0x1000))
+    }
+
+    #[test]
+    fn powers_on_with_the_last_bank_fixed() {
+        let mmc1 = test_mmc1();
+        assert_eq!(prg_banks(&mmc1), (0, 7));
+    }
+
+    #[test]
+    fn loads_registers_on_the_fifth_write() {
+        let mut mmc1 = test_mmc1();
+        for bit in 0..4 {
+            mmc1.cpu_write(0xE000, (0b00101 >> bit) & 0b1);
+            assert_eq!(prg_banks(&mmc1), (0, 7));
+        }
+        // Only the address of the fifth write picks the register.
+        mmc1.cpu_write(0xE000, 0);
+        assert_eq!(prg_banks(&mmc1), (5, 7));
+    }
+
+    #[test]
+    fn bit_7_resets_the_shift_register() {
+        let mut mmc1 = test_mmc1();
+        write_register(&mut mmc1, 0x8000, 0b01000);
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_write(0xE000, 0x80);
+        write_register(&mut mmc1, 0xE000, 0b00010);
+        // The reset also put the PRG mode back to 3.
+        assert_eq!(prg_banks(&mmc1), (2, 7));
+    }
+
+    #[test]
+    fn banks_prg_rom() {
+        let mut mmc1 = test_mmc1();
+        write_register(&mut mmc1, 0xE000, 5);
+        // 32KB mode ignores the low bit of the bank number.
+        write_register(&mut mmc1, 0x8000, 0b00000);
+        assert_eq!(prg_banks(&mmc1), (4, 5));
+        // First bank fixed at 0x8000.
+        write_register(&mut mmc1, 0x8000, 0b01000);
+        assert_eq!(prg_banks(&mmc1), (0, 5));
+        // Last bank fixed at 0xC000.
+        write_register(&mut mmc1, 0x8000, 0b01100);
+        assert_eq!(prg_banks(&mmc1), (5, 7));
+        // Bank numbers wrap around the ROM size.
+        write_register(&mut mmc1, 0xE000, 9);
+        assert_eq!(prg_banks(&mmc1), (1, 7));
+    }
+
+    #[test]
+    fn banks_chr() {
+        let mut mmc1 = test_mmc1();
+        write_register(&mut mmc1, 0xA000, 3);
+        write_register(&mut mmc1, 0xC000, 6);
+        // 8KB mode ignores the low bit and the second register.
+        assert_eq!(chr_banks(&mmc1), (2, 3));
+        write_register(&mut mmc1, 0x8000, 0b11100);
+        assert_eq!(chr_banks(&mmc1), (3, 6));
+    }
+
+    #[test]
+    fn sets_mirroring() {
+        let mut mmc1 = test_mmc1();
+        let modes = [
+            MirroringType::SingleScreenLower,
+            MirroringType::SingleScreenUpper,
+            MirroringType::Vertical,
+            MirroringType::Horizontal,
+        ];
+        for (control, mirroring) in modes.into_iter().enumerate() {
+            write_register(&mut mmc1, 0x8000, 0b01100 | control as u8);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn disables_prg_ram() {
+        let mut mmc1 = test_mmc1();
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+
+        write_register(&mut mmc1, 0xE000, 0x10);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        mmc1.cpu_write(0x6000, 0x99);
+
+        write_register(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+    }
+}
@@ -0,0 +1,397 @@
+use super::{bank_count, read_prg_ram, write_prg_ram, Mapper};
+use crate::cartridge::{ChrMemory, MirroringType};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub struct MMC3 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    bank_select_register: u8,
+    bank_registers: [u8; 8],
+    mirroring: MirroringType,
+    prg_ram_protect: u8,
+    prg_banks: usize,
+    chr_banks: usize,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool,
+}
+
+impl MMC3 {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr: ChrMemory,
+        prg_ram_size: usize,
+        mirroring: MirroringType,
+    ) -> Self {
+        let prg_banks = bank_count(prg_rom.len(), 0x2000);
+        let chr_banks = bank_count(chr.len(), 0x400);
+        MMC3 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            bank_select_register: 0,
+            bank_registers: [0; 8],
+            mirroring,
+            // Games that never touch the protect register still expect
+            // working PRG RAM, so start with it enabled and writable.
+            prg_ram_protect: 0x80,
+            // Count of 8KB PRG banks and 1KB CHR banks.
+            prg_banks,
+            chr_banks,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+        }
+    }
+
+    /*
+     *   PRG RAM protect (0xA001 - 0xBFFF, odd)
+     *
+     *   7  bit  0
+     *   ---- ----
+     *   RWXX xxxx
+     *   ||||
+     *   ||++------ Nothing on the MMC3, see MMC6
+     *   |+-------- Write protection (0: allow writes; 1: deny writes)
+     *   +--------- PRG RAM chip enable (0: disable; 1: enable)
+     */
+    fn prg_ram_readable(&self) -> bool {
+        self.prg_ram_protect & 0x80 != 0
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_readable() && self.prg_ram_protect & 0x40 == 0
+    }
+
+    // The scanline counter is clocked on each rising edge of PPU A12, which
+    // happens once per scanline when the background and sprites use
+    // different pattern tables.
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    /*
+     *   Bank select (0x8000 - 0x9FFE, even)
+     *
+     *   7  bit  0
+     *   ---- ----
+     *   CPMx xRRR
+     *   |||   |||
+     *   |||   +++- Specify which bank register to update on next write to Bank Data register
+     *   |||          000: R0: Select 2 KB CHR bank at PPU 0x0000-0x07FF (or 0x1000-0x17FF)
+     *   |||          001: R1: Select 2 KB CHR bank at PPU 0x0800-0x0FFF (or 0x1800-0x1FFF)
+     *   |||          010: R2: Select 1 KB CHR bank at PPU 0x1000-0x13FF (or 0x0000-0x03FF)
+     *   |||          011: R3: Select 1 KB CHR bank at PPU 0x1400-0x17FF (or 0x0400-0x07FF)
+     *   |||          100: R4: Select 1 KB CHR bank at PPU 0x1800-0x1BFF (or 0x0800-0x0BFF)
+     *   |||          101: R5: Select 1 KB CHR bank at PPU 0x1C00-0x1FFF (or 0x0C00-0x0FFF)
+     *   |||          110: R6: Select 8 KB PRG ROM bank at 0x8000-0x9FFF (or 0xC000-0xDFFF)
+     *   |||          111: R7: Select 8 KB PRG ROM bank at 0xA000-0xBFFF
+     *   ||+------- Nothing on the MMC3, see MMC6
+     *   |+-------- PRG ROM bank mode (0: 0x8000-0x9FFF swappable, 0xC000-0xDFFF fixed to second-last bank;
+     *   |                             1: 0xC000-0xDFFF swappable, 0x8000-0x9FFF fixed to second-last bank)
+     *   +--------- CHR A12 inversion (0: two 2 KB banks at 0x0000-0x0FFF, four 1 KB banks at 0x1000-0x1FFF;
+     *                                 1: two 2 KB banks at 0x1000-0x1FFF, four 1 KB banks at 0x0000-0x0FFF)
+     */
+
+    fn map_prg(&self, address: u16) -> u32 {
+        let prg_mode = self.bank_select_register & 0x40 != 0;
+        let second_last = self.prg_banks.saturating_sub(2);
+        let bank = match (prg_mode, address) {
+            (false, 0x8000..=0x9FFF) => self.bank_registers[6] as usize,
+            (true, 0x8000..=0x9FFF) => second_last,
+            (_, 0xA000..=0xBFFF) => self.bank_registers[7] as usize,
+            (false, 0xC000..=0xDFFF) => second_last,
+            (true, 0xC000..=0xDFFF) => self.bank_registers[6] as usize,
+            (_, _) => self.prg_banks - 1,
+        };
+        let bank = bank % self.prg_banks;
+        (0x2000 * bank + (address & 0x1FFF) as usize) as u32
+    }
+
+    fn map_chr(&self, address: u16) -> u32 {
+        // Inverting A12 swaps the 2KB and 1KB bank halves.
+        let address = match self.bank_select_register & 0x80 != 0 {
+            true => address ^ 0x1000,
+            false => address,
+        };
+        // Which 1KB half of a 2KB bank is being read.
+        let half = (address >> 10 & 0b1) as usize;
+        let bank = match address {
+            0x0000..=0x07FF => (self.bank_registers[0] & 0xFE) as usize + half,
+            0x0800..=0x0FFF => (self.bank_registers[1] & 0xFE) as usize + half,
+            0x1000..=0x13FF => self.bank_registers[2] as usize,
+            0x1400..=0x17FF => self.bank_registers[3] as usize,
+            0x1800..=0x1BFF => self.bank_registers[4] as usize,
+            _ => self.bank_registers[5] as usize,
+        };
+        let bank = bank % self.chr_banks;
+        (0x400 * bank + (address & 0x3FF) as usize) as u32
+    }
+}
+
+impl Mapper for MMC3 {
+    fn cpu_read(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_readable() => read_prg_ram(&self.prg_ram, address),
+            0x8000..=0xFFFF => self.prg_rom[self.map_prg(address) as usize],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match (address, address & 0b1) {
+            (0x6000..=0x7FFF, _) => {
+                if self.prg_ram_writable() {
+                    write_prg_ram(&mut self.prg_ram, address, value);
+                }
+            }
+            (0x0000..=0x5FFF, _) => {}
+            (0x8000..=0x9FFF, 0) => self.bank_select_register = value,
+            (0x8000..=0x9FFF, _) => {
+                let register = self.bank_select_register & 0b111;
+                self.bank_registers[register as usize] = value;
+            }
+            (0xA000..=0xBFFF, 0) => {
+                // Boards wired for four screen VRAM ignore this register.
+                self.mirroring = match (self.mirroring, value & 0b1) {
+                    (MirroringType::FourScreen, _) => MirroringType::FourScreen,
+                    (_, 0) => MirroringType::Vertical,
+                    (_, _) => MirroringType::Horizontal,
+                };
+            }
+            (0xA000..=0xBFFF, _) => self.prg_ram_protect = value,
+            (0xC000..=0xDFFF, 0) => self.irq_latch = value,
+            (0xC000..=0xDFFF, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (_, 0) => {
+                // Disabling IRQs also acknowledges any pending interrupt.
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (_, _) => self.irq_enabled = true,
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(self.map_chr(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.map_chr(address), value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn ppu_bus_address(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 {
+            self.clock_irq_counter();
+        }
+        self.a12 = a12;
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for MMC3 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_vec(&self.prg_ram);
+        state.write_u8(self.bank_select_register);
+        state.write_bytes(&self.bank_registers);
+        // Four screen boards never change mirroring, so only the 0xA000
+        // setting needs keeping.
+        state.write_bool(self.mirroring == MirroringType::Horizontal);
+        state.write_u8(self.prg_ram_protect);
+        state.write_u8(self.irq_latch);
+        state.write_u8(self.irq_counter);
+        state.write_bool(self.irq_reload);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.a12);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_vec(&mut self.prg_ram)?;
+        self.bank_select_register = state.read_u8()?;
+        state.read_bytes(&mut self.bank_registers)?;
+        let horizontal = state.read_bool()?;
+        if self.mirroring != MirroringType::FourScreen {
+            self.mirroring = match horizontal {
+                true => MirroringType::Horizontal,
+                false => MirroringType::Vertical,
+            };
+        }
+        self.prg_ram_protect = state.read_u8()?;
+        self.irq_latch = state.read_u8()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_reload = state.read_bool()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.a12 = state.read_bool()?;
+        self.chr.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 PRG and CHR banks, each filled with its own bank number.
+    fn test_mmc3(mirroring: MirroringType) -> MMC3 {
+        let prg_rom = (0..16).flat_map(|bank| vec![bank; 0x2000]).collect();
+        let chr_rom = (0..16).flat_map(|bank| vec![bank; 0x400]).collect();
+        MMC3::new(prg_rom, ChrMemory::rom(chr_rom), 0x2000, mirroring)
+    }
+
+    fn set_bank(mmc3: &mut MMC3, mode: u8, register: u8, bank: u8) {
+        mmc3.cpu_write(0x8000, mode | register);
+        mmc3.cpu_write(0x8001, bank);
+    }
+
+    fn prg_banks(mmc3: &MMC3) -> [u8; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|address| mmc3.cpu_read(address))
+    }
+
+    fn chr_banks(mmc3: &MMC3) -> [u8; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|bank| mmc3.ppu_read(bank * 0x400))
+    }
+
+    // Put PPU A12 low then high, as happens once per scanline.
+    fn clock_scanline(mmc3: &mut MMC3) {
+        mmc3.ppu_bus_address(0x0000);
+        mmc3.ppu_bus_address(0x1000);
+    }
+
+    #[test]
+    fn banks_prg_rom() {
+        let mut mmc3 = test_mmc3(MirroringType::Vertical);
+        set_bank(&mut mmc3, 0, 6, 3);
+        set_bank(&mut mmc3, 0, 7, 4);
+        assert_eq!(prg_banks(&mmc3), [3, 4, 14, 15]);
+        // PRG mode 1 swaps the switchable and second last banks.
+        mmc3.cpu_write(0x8000, 0x40);
+        assert_eq!(prg_banks(&mmc3), [14, 4, 3, 15]);
+        // Bank numbers wrap around the ROM size.
+        set_bank(&mut mmc3, 0x40, 7, 17);
+        assert_eq!(prg_banks(&mmc3), [14, 1, 3, 15]);
+    }
+
+    #[test]
+    fn banks_chr() {
+        let mut mmc3 = test_mmc3(MirroringType::Vertical);
+        // 2KB banks ignore the low bit.
+        set_bank(&mut mmc3, 0, 0, 5);
+        set_bank(&mut mmc3, 0, 1, 6);
+        for register in 2..6 {
+            set_bank(&mut mmc3, 0, register, register + 6);
+        }
+        assert_eq!(chr_banks(&mmc3), [4, 5, 6, 7, 8, 9, 10, 11]);
+        // Inverting A12 swaps the 2KB and 1KB halves.
+        mmc3.cpu_write(0x8000, 0x80);
+        assert_eq!(chr_banks(&mmc3), [8, 9, 10, 11, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn counts_scanlines_down_to_an_irq() {
+        let mut mmc3 = test_mmc3(MirroringType::Vertical);
+        mmc3.cpu_write(0xC000, 2);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+        // Reloaded to 2, then counted down to 1 and 0.
+        for _ in 0..2 {
+            clock_scanline(&mut mmc3);
+            assert!(!mmc3.irq_pending());
+        }
+        clock_scanline(&mut mmc3);
+        assert!(mmc3.irq_pending());
+
+        // Writing 0xE000 acknowledges the IRQ and disables more.
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq_pending());
+        for _ in 0..6 {
+            clock_scanline(&mut mmc3);
+        }
+        assert!(!mmc3.irq_pending());
+
+        // The counter carries on from where it was once enabled again:
+        // 0 reloads to 2, then 1, then 0.
+        mmc3.cpu_write(0xE001, 0);
+        for _ in 0..3 {
+            clock_scanline(&mut mmc3);
+        }
+        assert!(mmc3.irq_pending());
+    }
+
+    #[test]
+    fn only_rising_a12_clocks_the_counter() {
+        let mut mmc3 = test_mmc3(MirroringType::Vertical);
+        mmc3.cpu_write(0xC000, 1);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+        clock_scanline(&mut mmc3);
+        // A12 staying high isn't an edge.
+        for _ in 0..8 {
+            mmc3.ppu_bus_address(0x1FF0);
+        }
+        assert!(!mmc3.irq_pending());
+        clock_scanline(&mut mmc3);
+        assert!(mmc3.irq_pending());
+    }
+
+    #[test]
+    fn sets_mirroring() {
+        let mut mmc3 = test_mmc3(MirroringType::Vertical);
+        mmc3.cpu_write(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), MirroringType::Horizontal);
+        mmc3.cpu_write(0xA000, 0);
+        assert_eq!(mmc3.mirroring(), MirroringType::Vertical);
+
+        let mut mmc3 = test_mmc3(MirroringType::FourScreen);
+        mmc3.cpu_write(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), MirroringType::FourScreen);
+    }
+
+    #[test]
+    fn protects_prg_ram() {
+        let mut mmc3 = test_mmc3(MirroringType::Vertical);
+        mmc3.cpu_write(0x7FFF, 0x42);
+        assert_eq!(mmc3.cpu_read(0x7FFF), 0x42);
+        // Write protected.
+        mmc3.cpu_write(0xA001, 0xC0);
+        mmc3.cpu_write(0x7FFF, 0x99);
+        assert_eq!(mmc3.cpu_read(0x7FFF), 0x42);
+        // Disabled.
+        mmc3.cpu_write(0xA001, 0x00);
+        assert_eq!(mmc3.cpu_read(0x7FFF), 0);
+    }
+}
@@ -1,19 +1,64 @@
-use super::Mapper;
+use super::{read_prg_ram, write_prg_ram, Mapper};
+use crate::cartridge::{ChrMemory, MirroringType};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
-pub struct NROM {}
+pub struct NROM {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    mirroring: MirroringType,
+}
 
 impl NROM {
-    pub fn new() -> Self {
-        NROM {}
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize, mirroring: MirroringType) -> Self {
+        NROM {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            mirroring,
+        }
     }
 }
 
 impl Mapper for NROM {
-    fn map_prg(&self, address: u16) -> u32 {
-        (address - 0x8000) as u32
+    fn cpu_read(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF => read_prg_ram(&self.prg_ram, address),
+            // NROM-128 only has 16KB of PRG ROM, mirrored into 0xC000 - 0xFFFF.
+            0x8000..=0xFFFF => self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if (0x6000..=0x7FFF).contains(&address) {
+            write_prg_ram(&mut self.prg_ram, address, value);
+        }
+    }
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(address as u32)
+    }
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as u32, value);
+    }
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
     }
-    fn map_chr(&self, address: u16) -> u32 {
-        address as u32
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for NROM {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_vec(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_vec(&mut self.prg_ram)?;
+        self.chr.load_state(state)
     }
-    fn bank_select(&mut self, value: u8) {}
 }
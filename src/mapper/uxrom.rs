@@ -1,41 +1,79 @@
-use super::Mapper;
+use super::{bank_count, Mapper};
+use crate::cartridge::{ChrMemory, MirroringType};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 pub struct UXROM {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
     bank_select_register: u8,
-    prg_banks: u8, 
+    prg_banks: usize,
+    mirroring: MirroringType,
 }
 
 impl UXROM {
-    pub fn new(banks: u8) -> Self {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, mirroring: MirroringType) -> Self {
+        let prg_banks = bank_count(prg_rom.len(), 0x4000);
         UXROM {
+            prg_rom,
+            chr,
             bank_select_register: 0x00,
-            prg_banks: banks,
+            prg_banks,
+            mirroring,
         }
     }
-}
 
-impl Mapper for UXROM {
     /*
     *   0x8000 - 0xBFFF = Switchable PRG ROM 
     *   0xC000 - 0xFFFF = Fixed PRG ROM
     */
 
     fn map_prg(&self, address: u16) -> u32 {
-        let bank = match address <= 0xC000 { 
+        let bank = match address < 0xC000 { 
             // If address is in switchable bank address space...
-            true => self.bank_select_register,
+            true => self.bank_select_register as usize % self.prg_banks,
             // Since 0xC000-0xFFFF is fixed to the last bank, need to sub one.
-            false => self.prg_banks - 1, 
-        } as u32;
-        let mapped_address = (address & 0x3FFF) as u32;
+            false => self.prg_banks - 1,
+        };
+        let mapped_address = (address & 0x3FFF) as usize;
         (0x4000 * bank + mapped_address) as u32
     }
+}
+
+impl Mapper for UXROM {
+    fn cpu_read(&self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.prg_rom[self.map_prg(address) as usize],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.bank_select_register = value & 0x0F;
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(address as u32)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as u32, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+}
 
-    fn map_chr(&self, address: u16) -> u32 {
-        address as u32
+impl Snapshot for UXROM {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.bank_select_register);
+        self.chr.save_state(state);
     }
 
-    fn bank_select(&mut self, value: u8) {
-        self.bank_select_register = (value & 0x0F);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bank_select_register = state.read_u8()?;
+        self.chr.load_state(state)
     }
 }
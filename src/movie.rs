@@ -0,0 +1,384 @@
+use crate::joypad::JoypadButton;
+use std::fmt;
+
+// Button order of a gamepad field in an FM2 input line, from bit 7 to bit 0
+// of JoypadButton.
+const FM2_BUTTONS: [char; 8] = ['R', 'L', 'D', 'U', 'T', 'S', 'B', 'A'];
+// FM2 command bits.
+const COMMAND_SOFT_RESET: u8 = 0b1;
+const COMMAND_HARD_RESET: u8 = 0b10;
+// Our own header key for movies that start from a save state. FCEUX's
+// `savestate` key holds its own state format, which we can't read.
+const START_STATE_KEY: &str = "nesemuSavestate";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovieError {
+    // FM2 files must start with "version 3".
+    UnsupportedVersion,
+    // Line number (starting at 1) and a description of what's wrong with it.
+    InvalidLine(usize, String),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::UnsupportedVersion => write!(f, "not a version 3 FM2 movie"),
+            MovieError::InvalidLine(line, reason) => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovieFrame {
+    // Reset commands to apply before the frame runs.
+    pub commands: u8,
+    pub buttons: [JoypadButton; 2],
+}
+
+impl MovieFrame {
+    pub fn new(buttons: [JoypadButton; 2]) -> Self {
+        MovieFrame {
+            commands: 0,
+            buttons,
+        }
+    }
+
+    // We have no separate power cycle, so both kinds of reset press the
+    // reset button.
+    pub fn reset(&self) -> bool {
+        self.commands & (COMMAND_SOFT_RESET | COMMAND_HARD_RESET) != 0
+    }
+
+    pub fn press_reset(&mut self) {
+        self.commands |= COMMAND_SOFT_RESET;
+    }
+}
+
+/*
+ *   Input movie
+ *
+ *   The buttons held on each port for every frame, from power on or from a
+ *   save state. Replaying a movie through Nes::step_frame from the same
+ *   starting point reproduces the original run exactly.
+ *
+ *   Movies are stored in FCEUX's text based FM2 format:
+ *   https://fceux.com/web/FM2.html
+ */
+#[derive(Debug, Clone)]
+pub struct Movie {
+    pub rom_filename: String,
+    pub pal: bool,
+    pub rerecord_count: u32,
+    pub comments: Vec<String>,
+    // Save state the movie starts from, or None to start from power on.
+    pub start_state: Option<Vec<u8>>,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(rom_filename: &str, start_state: Option<Vec<u8>>) -> Self {
+        Movie {
+            rom_filename: rom_filename.to_string(),
+            pal: false,
+            rerecord_count: 0,
+            comments: Vec::new(),
+            start_state,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, frame: MovieFrame) {
+        self.frames.push(frame);
+    }
+
+    // Input for the given frame, None once the movie has ended.
+    pub fn frame(&self, index: usize) -> Option<MovieFrame> {
+        self.frames.get(index).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Drop frames after `length`, e.g. when rewinding while recording.
+    pub fn truncate(&mut self, length: usize) {
+        self.frames.truncate(length);
+    }
+
+    pub fn from_fm2(contents: &str) -> Result<Movie, MovieError> {
+        let mut lines = contents.lines().enumerate();
+        match lines.next() {
+            Some((_, line)) if line.trim() == "version 3" => {}
+            _ => return Err(MovieError::UnsupportedVersion),
+        }
+
+        let mut movie = Movie::new("", None);
+        for (index, line) in lines {
+            let invalid = |reason: String| MovieError::InvalidLine(index + 1, reason);
+            if line.starts_with('|') {
+                movie.frames.push(Self::parse_frame(line).map_err(invalid)?);
+                continue;
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "palFlag" => movie.pal = value == "1",
+                "rerecordCount" => {
+                    movie.rerecord_count = value
+                        .parse()
+                        .map_err(|_| invalid(format!("invalid rerecord count '{}'", value)))?
+                }
+                "comment" => movie.comments.push(value.to_string()),
+                START_STATE_KEY => {
+                    let state = value
+                        .strip_prefix("base64:")
+                        .and_then(base64_decode)
+                        .ok_or_else(|| invalid("invalid save state".to_string()))?;
+                    movie.start_state = Some(state);
+                }
+                // Everything else describes FCEUX's setup (GUID, checksum,
+                // expansion ports) and doesn't affect playback here.
+                _ => {}
+            }
+        }
+        Ok(movie)
+    }
+
+    // An input line looks like |commands|port0|port1|port2|, where each
+    // gamepad field has one character per button in RLDUTSBA order. A space
+    // or '.' means the button is up, anything else that it's held.
+    fn parse_frame(line: &str) -> Result<MovieFrame, String> {
+        let fields: Vec<&str> = line.split('|').collect();
+        if fields.len() < 4 {
+            return Err(format!(
+                "expected |commands|port0|port1|port2|, found '{}'",
+                line
+            ));
+        }
+        let commands = fields[1]
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid commands '{}'", fields[1]))?;
+        let mut buttons = [JoypadButton::empty(); 2];
+        for (port, field) in fields[2..4].iter().enumerate() {
+            if field.is_empty() {
+                continue;
+            }
+            if field.chars().count() != FM2_BUTTONS.len() {
+                return Err(format!("invalid gamepad input '{}'", field));
+            }
+            for (bit, button) in field.chars().enumerate() {
+                if button != '.' && button != ' ' {
+                    buttons[port].insert(JoypadButton::from_bits_retain(0x80 >> bit));
+                }
+            }
+        }
+        Ok(MovieFrame { commands, buttons })
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let second_port = self.frames.iter().any(|frame| !frame.buttons[1].is_empty());
+        let mut fm2 = String::new();
+        let mut header = |key: &str, value: &str| {
+            fm2.push_str(key);
+            fm2.push(' ');
+            fm2.push_str(value);
+            fm2.push('\n');
+        };
+        header("version", "3");
+        header("emuVersion", "22020");
+        header("rerecordCount", &self.rerecord_count.to_string());
+        header("palFlag", if self.pal { "1" } else { "0" });
+        header("romFilename", &self.rom_filename);
+        // We don't compute FCEUX's MD5 checksum, which it only warns about.
+        header("romChecksum", "base64:AAAAAAAAAAAAAAAAAAAAAA==");
+        header("guid", "00000000-0000-0000-0000-000000000000");
+        header("fourscore", "0");
+        header("microphone", "0");
+        header("port0", "1");
+        header("port1", if second_port { "1" } else { "0" });
+        header("port2", "0");
+        header("FDS", "0");
+        header("NewPPU", "0");
+        for comment in &self.comments {
+            header("comment", comment);
+        }
+        if let Some(state) = &self.start_state {
+            header(START_STATE_KEY, &format!("base64:{}", base64_encode(state)));
+        }
+
+        for frame in &self.frames {
+            fm2.push('|');
+            fm2.push_str(&frame.commands.to_string());
+            fm2.push('|');
+            Self::write_buttons(&mut fm2, frame.buttons[0]);
+            fm2.push('|');
+            if second_port {
+                Self::write_buttons(&mut fm2, frame.buttons[1]);
+            }
+            fm2.push_str("||\n");
+        }
+        fm2
+    }
+
+    fn write_buttons(fm2: &mut String, buttons: JoypadButton) {
+        for (bit, button) in FM2_BUTTONS.iter().enumerate() {
+            match buttons.bits() & (0x80 >> bit) != 0 {
+                true => fm2.push(*button),
+                false => fm2.push('.'),
+            }
+        }
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut output = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => output
+                    .push(BASE64_ALPHABET[(group >> (18 - 6 * index) & 0x3F) as usize] as char),
+                false => output.push('='),
+            }
+        }
+    }
+    output
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut output = Vec::with_capacity(text.len() * 3 / 4);
+    let mut group: u32 = 0;
+    let mut bits = 0;
+    for character in text.bytes() {
+        let value = BASE64_ALPHABET.iter().position(|c| *c == character)? as u32;
+        group = group << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((group >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FM2: &str = "\
+version 3
+emuVersion 22020
+rerecordCount 5
+palFlag 0
+romFilename game
+guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+comment author someone
+port0 1
+port1 1
+port2 0
+|0|........|........||
+|1|R......A|........||
+|0|RLDUTSBA|...U....||
+|2|   U    |||
+";
+
+    #[test]
+    fn parses_fm2() {
+        let movie = Movie::from_fm2(FM2).unwrap();
+        assert_eq!(movie.rom_filename, "game");
+        assert!(!movie.pal);
+        assert_eq!(movie.rerecord_count, 5);
+        assert_eq!(movie.comments, ["author someone"]);
+        assert_eq!(movie.start_state, None);
+        assert_eq!(movie.len(), 4);
+
+        assert_eq!(movie.frame(0), Some(MovieFrame::new([JoypadButton::empty(); 2])));
+        let frame = movie.frame(1).unwrap();
+        assert!(frame.reset());
+        assert_eq!(frame.buttons[0], JoypadButton::RIGHT | JoypadButton::A);
+        let frame = movie.frame(2).unwrap();
+        assert!(!frame.reset());
+        assert_eq!(frame.buttons[0], JoypadButton::all());
+        assert_eq!(frame.buttons[1], JoypadButton::UP);
+        // Hard reset, spaces for released buttons and an empty second port.
+        let frame = movie.frame(3).unwrap();
+        assert!(frame.reset());
+        assert_eq!(frame.buttons, [JoypadButton::UP, JoypadButton::empty()]);
+        assert_eq!(movie.frame(4), None);
+    }
+
+    #[test]
+    fn fm2_round_trip() {
+        let mut movie = Movie::new("game", Some(vec![0, 1, 2, 3, 0xFE, 0xFF, 0x80]));
+        movie.rerecord_count = 3;
+        movie.comments.push("author someone".to_string());
+        let mut frame = MovieFrame::new([JoypadButton::START, JoypadButton::B]);
+        frame.press_reset();
+        movie.record(frame);
+        movie.record(MovieFrame::new([JoypadButton::LEFT | JoypadButton::A, JoypadButton::empty()]));
+
+        let parsed = Movie::from_fm2(&movie.to_fm2()).unwrap();
+        assert_eq!(parsed.rom_filename, movie.rom_filename);
+        assert_eq!(parsed.rerecord_count, movie.rerecord_count);
+        assert_eq!(parsed.comments, movie.comments);
+        assert_eq!(parsed.start_state, movie.start_state);
+        assert_eq!(parsed.frames, movie.frames);
+    }
+
+    #[test]
+    fn leaves_out_an_unused_second_port() {
+        let mut movie = Movie::new("game", None);
+        movie.record(MovieFrame::new([JoypadButton::SELECT, JoypadButton::empty()]));
+        let fm2 = movie.to_fm2();
+        assert!(fm2.contains("port1 0\n"));
+        assert!(fm2.ends_with("|0|.....S..|||\n"));
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        for length in 0..8 {
+            let data: Vec<u8> = (0..length).map(|i| (i * 37) as u8).collect();
+            assert_eq!(base64_decode(&base64_encode(&data)), Some(data));
+        }
+        assert_eq!(base64_encode(b"NES"), "TkVT");
+        assert_eq!(base64_encode(b"NE"), "TkU=");
+        assert_eq!(base64_decode("not base64!"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_fm2() {
+        assert_eq!(Movie::from_fm2("version 2\n").err(), Some(MovieError::UnsupportedVersion));
+        assert!(matches!(
+            Movie::from_fm2("version 3\n|0|RLD|........||\n"),
+            Err(MovieError::InvalidLine(2, _))
+        ));
+        assert!(matches!(
+            Movie::from_fm2("version 3\n|x|........|........||\n"),
+            Err(MovieError::InvalidLine(2, _))
+        ));
+        assert!(matches!(
+            Movie::from_fm2("version 3\nrerecordCount many\n"),
+            Err(MovieError::InvalidLine(2, _))
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::bus::Bus;
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::cpu::CPU;
+use crate::joypad::JoypadButton;
+use crate::movie::MovieFrame;
+use crate::ppu::frame::{FrameBuffer, DEFAULT_PALETTE};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+ *   Entry point for embedding the emulator.
+ *
+ *   Wires a cartridge up to the CPU, bus, PPU and APU and lets the caller
+ *   drive the machine one instruction, scanline or frame at a time. Nothing
+ *   here depends on a window or audio device, so it runs the same from
+ *   tests, bots and alternative frontends.
+ */
+pub struct Nes {
+    pub cpu: CPU,
+    frame: FrameBuffer,
+    palette: Vec<(u8, u8, u8)>,
+    // Set once the CPU stops at BRK or an opcode it can't execute.
+    halted: bool,
+}
+
+impl Nes {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let mut cpu = CPU::new(Bus::new(cartridge));
+        cpu.reset();
+        Nes {
+            cpu,
+            frame: FrameBuffer::new(),
+            palette: DEFAULT_PALETTE.to_vec(),
+            halted: false,
+        }
+    }
+
+    // Parse an iNES / NES 2.0 image and build a console around it.
+    pub fn from_rom(binary: &[u8]) -> Result<Self, CartridgeError> {
+        Ok(Nes::new(Cartridge::new(binary)?))
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.halted = false;
+    }
+
+    // Whether the CPU has stopped. Nothing more runs until the console is
+    // reset or a state is loaded.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    // Execute a single instruction unless the CPU has halted, returning
+    // whether it's still running.
+    fn step_cpu(&mut self) -> bool {
+        if !self.halted {
+            self.halted = !self.cpu.step();
+        }
+        !self.halted
+    }
+
+    // Execute a single instruction, servicing any pending interrupt first.
+    // Returns the number of CPU cycles it took.
+    pub fn step_instruction(&mut self) -> usize {
+        let cycles = self.cycles();
+        self.step_cpu();
+        self.cycles() - cycles
+    }
+
+    // Run until the PPU moves on to the next scanline, or the CPU halts.
+    pub fn step_scanline(&mut self) {
+        let scanline = self.cpu.bus.ppu().scanline();
+        while self.cpu.bus.ppu().scanline() == scanline && self.step_cpu() {}
+    }
+
+    // Run until the PPU finishes the visible part of the next frame, then
+    // render it. Stops early if the CPU halts.
+    pub fn step_frame(&mut self) -> &FrameBuffer {
+        while !self.cpu.bus.poll_frame_complete() && self.step_cpu() {}
+        self.render_frame()
+    }
+
+    // Apply one frame of movie input, then run the frame.
+    pub fn step_movie_frame(&mut self, input: &MovieFrame) -> &FrameBuffer {
+        if input.reset() {
+            self.reset();
+        }
+        for (port, buttons) in input.buttons.iter().enumerate() {
+            self.set_buttons(port, *buttons);
+        }
+        self.step_frame()
+    }
+
+    // Colour in whatever the PPU has drawn so far without running the
+    // machine. The picture isn't part of save states, so after loading one
+    // this still shows the frame from before until the next one is run.
+    pub fn render_frame(&mut self) -> &FrameBuffer {
+        FrameBuffer::render(self.cpu.bus.ppu(), &mut self.frame, self.palette.clone());
+        &self.frame
+    }
+
+    // The most recently rendered frame.
+    pub fn frame(&self) -> &FrameBuffer {
+        &self.frame
+    }
+
+    // Set which buttons are held on the controller plugged into `port`,
+    // 0 for player 1 and 1 for player 2.
+    pub fn set_buttons(&mut self, port: usize, buttons: JoypadButton) {
+        self.cpu.bus.joypad_mut(port).set_buttons(buttons);
+    }
+
+    // Serialize the whole machine, see state.rs for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        self.cpu.save_state(&mut state);
+        state.finish()
+    }
+
+    // Restore a state made by save_state with the same cartridge. The
+    // machine is left untouched if the state can't be loaded.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        let result = self.restore_state(data);
+        if result.is_ok() {
+            self.halted = false;
+        } else {
+            self.restore_state(&backup)
+                .expect("restoring a state that was just saved");
+        }
+        result
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data)?;
+        self.cpu.load_state(&mut state)?;
+        if !state.is_empty() {
+            return Err(StateError::Invalid("unexpected data after the end of the state"));
+        }
+        Ok(())
+    }
+
+    // CPU cycles elapsed since power on.
+    pub fn cycles(&self) -> usize {
+        self.cpu.bus.cycles()
+    }
+
+    pub fn set_palette(&mut self, palette: Vec<(u8, u8, u8)>) {
+        self.palette = palette;
+    }
+
+    // Hardware draws at most 8 sprites a line. Lifting the limit gets rid of
+    // flicker, though a few games rely on it to hide sprites.
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.cpu.bus.set_sprite_limit(enabled);
+    }
+
+    pub fn set_audio_output(&mut self, clock_rate: u32, sample_rate: u32) {
+        self.cpu.bus.set_audio_output(clock_rate, sample_rate);
+    }
+
+    // Audio samples produced since the last call, at the rate given to
+    // set_audio_output.
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.bus.take_audio_samples()
+    }
+}
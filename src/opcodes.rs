@@ -3,6 +3,8 @@ use std::collections::HashMap;
 
 pub struct Opcode {
     pub instruction: u8,
+    // Not read by the emulator, but keeps the opcode table readable.
+    #[allow(dead_code)]
     pub mnemonic: &'static str,
     pub length: u8,
     pub cycles: u8,
@@ -0,0 +1,75 @@
+use crate::cartridge::TimingMode;
+use std::time::{Duration, Instant};
+
+// https://www.nesdev.org/wiki/Cycle_reference_chart
+pub const NTSC_FRAME_RATE: f64 = 60.0988;
+pub const PAL_FRAME_RATE: f64 = 50.007;
+
+// How far the frame period may be stretched or squeezed to keep the audio
+// queue near its target depth. Half a percent is well under what anyone can
+// hear as a pitch change.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+// Give up on catching up once emulation falls this far behind, e.g. after
+// the window was dragged, rather than running flat out to make up the time.
+const MAX_FRAMES_BEHIND: u32 = 4;
+// Sleep until this close to the deadline, then spin, since sleeps can
+// overshoot by a millisecond or more.
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+pub fn frame_rate(timing: TimingMode) -> f64 {
+    match timing {
+        TimingMode::Pal | TimingMode::Dendy => PAL_FRAME_RATE,
+        TimingMode::Ntsc | TimingMode::MultiRegion => NTSC_FRAME_RATE,
+    }
+}
+
+/*
+ *   Keeps emulation running at the console's own frame rate, independent of
+ *   the refresh rate of the host display.
+ *
+ *   The pacer holds a deadline for each frame and sleeps until it. When
+ *   synced to audio it also nudges the frame period by the depth of the
+ *   audio queue (dynamic rate control), so the small difference between the
+ *   host's audio clock and its system clock can't slowly drain or flood the
+ *   queue.
+ */
+pub struct FramePacer {
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl FramePacer {
+    pub fn new(frame_rate: f64) -> Self {
+        FramePacer {
+            frame_duration: Duration::from_secs_f64(1.0 / frame_rate),
+            next_frame: Instant::now(),
+        }
+    }
+
+    // Block until the next frame is due. `audio_fill` is the depth of the
+    // audio queue relative to its target, where 1.0 is on target, or None
+    // when audio isn't being played.
+    pub fn wait(&mut self, audio_fill: Option<f64>) {
+        let adjustment = match audio_fill {
+            // A fuller queue slows emulation down, an emptier one speeds it up.
+            Some(fill) => ((fill - 1.0) * MAX_RATE_ADJUSTMENT)
+                .clamp(-MAX_RATE_ADJUSTMENT, MAX_RATE_ADJUSTMENT),
+            None => 0.0,
+        };
+        self.next_frame += self.frame_duration.mul_f64(1.0 + adjustment);
+
+        let now = Instant::now();
+        if now > self.next_frame + self.frame_duration * MAX_FRAMES_BEHIND {
+            self.next_frame = now;
+            return;
+        }
+        if let Some(remaining) = self.next_frame.checked_duration_since(now) {
+            if remaining > SPIN_THRESHOLD {
+                std::thread::sleep(remaining - SPIN_THRESHOLD);
+            }
+        }
+        while Instant::now() < self.next_frame {
+            std::hint::spin_loop();
+        }
+    }
+}
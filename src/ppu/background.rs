@@ -0,0 +1,100 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+ *   https://www.nesdev.org/wiki/PPU_rendering
+ *
+ *   Background tiles are fetched 8 dots ahead of being drawn. Each tile's
+ *   nametable byte, attribute bits and two pattern bytes are latched as they
+ *   are fetched, then loaded into the low half of 16-bit shift registers
+ *   while the tile before it is shifted out of the high half. Fine X scroll
+ *   picks which bit of the high half is drawn.
+ *
+ *   The attribute shift registers are really 8 bits wide, fed from a latch,
+ *   but filling them with the palette bits of a whole tile at once comes to
+ *   the same thing.
+ */
+pub struct Background {
+    pub next_tile: u8,
+    pub next_attribute: u8,
+    pub next_pattern_low: u8,
+    pub next_pattern_high: u8,
+    pattern_low: u16,
+    pattern_high: u16,
+    attribute_low: u16,
+    attribute_high: u16,
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Background {
+            next_tile: 0,
+            next_attribute: 0,
+            next_pattern_low: 0,
+            next_pattern_high: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+            attribute_low: 0,
+            attribute_high: 0,
+        }
+    }
+
+    // Move the latched tile into the shift registers, behind the one being
+    // drawn.
+    pub fn reload(&mut self) {
+        let fill = |bit: u8| if bit != 0 { 0xFF } else { 0x00 };
+        self.pattern_low = (self.pattern_low & 0xFF00) | self.next_pattern_low as u16;
+        self.pattern_high = (self.pattern_high & 0xFF00) | self.next_pattern_high as u16;
+        self.attribute_low = (self.attribute_low & 0xFF00) | fill(self.next_attribute & 0b1);
+        self.attribute_high = (self.attribute_high & 0xFF00) | fill(self.next_attribute & 0b10);
+    }
+
+    pub fn shift(&mut self) {
+        self.pattern_low <<= 1;
+        self.pattern_high <<= 1;
+        self.attribute_low <<= 1;
+        self.attribute_high <<= 1;
+    }
+
+    // The current pixel as a background palette entry, PPPP where the top
+    // two bits pick the palette and the bottom two the colour. Colour 0 is
+    // transparent.
+    pub fn pixel(&self, fine_x: u8) -> u8 {
+        let bit = 0x8000 >> fine_x;
+        let select = |register: u16, value: u8| if register & bit != 0 { value } else { 0 };
+        select(self.attribute_high, 0b1000)
+            | select(self.attribute_low, 0b100)
+            | select(self.pattern_high, 0b10)
+            | select(self.pattern_low, 0b1)
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for Background {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.next_tile);
+        state.write_u8(self.next_attribute);
+        state.write_u8(self.next_pattern_low);
+        state.write_u8(self.next_pattern_high);
+        state.write_u16(self.pattern_low);
+        state.write_u16(self.pattern_high);
+        state.write_u16(self.attribute_low);
+        state.write_u16(self.attribute_high);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.next_tile = state.read_u8()?;
+        self.next_attribute = state.read_u8()?;
+        self.next_pattern_low = state.read_u8()?;
+        self.next_pattern_high = state.read_u8()?;
+        self.pattern_low = state.read_u16()?;
+        self.pattern_high = state.read_u16()?;
+        self.attribute_low = state.read_u16()?;
+        self.attribute_high = state.read_u16()?;
+        Ok(())
+    }
+}
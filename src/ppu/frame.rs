@@ -1,86 +1,115 @@
-use crate::{cartridge::MirroringType, ppu::PPU};
+use crate::ppu::PPU;
 use rand::Rng;
-
-const WIDTH: usize = 256;
-const HEIGHT: usize = 240;
-
-pub struct Frame {
+use std::io;
+use std::path::Path;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+// RGB values for the 64 colours of the 2C02's system palette.
+pub const DEFAULT_PALETTE: [(u8, u8, u8); 64] = [
+    (0x80, 0x80, 0x80),
+    (0x00, 0x3D, 0xA6),
+    (0x00, 0x12, 0xB0),
+    (0x44, 0x00, 0x96),
+    (0xA1, 0x00, 0x5E),
+    (0xC7, 0x00, 0x28),
+    (0xBA, 0x06, 0x00),
+    (0x8C, 0x17, 0x00),
+    (0x5C, 0x2F, 0x00),
+    (0x10, 0x45, 0x00),
+    (0x05, 0x4A, 0x00),
+    (0x00, 0x47, 0x2E),
+    (0x00, 0x41, 0x66),
+    (0x00, 0x00, 0x00),
+    (0x05, 0x05, 0x05),
+    (0x05, 0x05, 0x05),
+    (0xC7, 0xC7, 0xC7),
+    (0x00, 0x77, 0xFF),
+    (0x21, 0x55, 0xFF),
+    (0x82, 0x37, 0xFA),
+    (0xEB, 0x2F, 0xB5),
+    (0xFF, 0x29, 0x50),
+    (0xFF, 0x22, 0x00),
+    (0xD6, 0x32, 0x00),
+    (0xC4, 0x62, 0x00),
+    (0x35, 0x80, 0x00),
+    (0x05, 0x8F, 0x00),
+    (0x00, 0x8A, 0x55),
+    (0x00, 0x99, 0xCC),
+    (0x21, 0x21, 0x21),
+    (0x09, 0x09, 0x09),
+    (0x09, 0x09, 0x09),
+    (0xFF, 0xFF, 0xFF),
+    (0x0F, 0xD7, 0xFF),
+    (0x69, 0xA2, 0xFF),
+    (0xD4, 0x80, 0xFF),
+    (0xFF, 0x45, 0xF3),
+    (0xFF, 0x61, 0x8B),
+    (0xFF, 0x88, 0x33),
+    (0xFF, 0x9C, 0x12),
+    (0xFA, 0xBC, 0x20),
+    (0x9F, 0xE3, 0x0E),
+    (0x2B, 0xF0, 0x35),
+    (0x0C, 0xF0, 0xA4),
+    (0x05, 0xFB, 0xFF),
+    (0x5E, 0x5E, 0x5E),
+    (0x0D, 0x0D, 0x0D),
+    (0x0D, 0x0D, 0x0D),
+    (0xFF, 0xFF, 0xFF),
+    (0xA6, 0xFC, 0xFF),
+    (0xB3, 0xEC, 0xFF),
+    (0xDA, 0xAB, 0xEB),
+    (0xFF, 0xA8, 0xF9),
+    (0xFF, 0xAB, 0xB3),
+    (0xFF, 0xD2, 0xB0),
+    (0xFF, 0xEF, 0xA6),
+    (0xFF, 0xF7, 0x9C),
+    (0xD7, 0xE8, 0x95),
+    (0xA6, 0xED, 0xAF),
+    (0xA2, 0xF2, 0xDA),
+    (0x99, 0xFF, 0xFC),
+    (0xDD, 0xDD, 0xDD),
+    (0x11, 0x11, 0x11),
+    (0x11, 0x11, 0x11),
+];
+
+pub struct FrameBuffer {
     pub frame_data: Vec<u8>,
 }
 
-struct Rectangle {
-    x_1: usize,
-    y_1: usize,
-    x_2: usize,
-    y_2: usize,
-}
-
-impl Rectangle {
-    fn new(x1: usize, y1: usize, x2: usize, y2: usize) -> Self {
-        Rectangle {
-            x_1: x1,
-            y_1: y1,
-            x_2: x2,
-            y_2: y2,
-        }
-    }
-}
-
-impl Frame {
+impl FrameBuffer {
     pub fn new() -> Self {
-        Frame {
+        FrameBuffer {
             frame_data: vec![0; WIDTH * HEIGHT * 3],
         }
     }
 
-    pub fn read_palette_from_file(file_name: &str) -> Vec<(u8, u8, u8)> {
+    // Palette files hold one colour per line as six hex digits, RRGGBB.
+    pub fn read_palette_from_file(file_name: &Path) -> io::Result<Vec<(u8, u8, u8)>> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
         let mut palette_vec: Vec<(u8, u8, u8)> = vec![];
-        for line in std::fs::read_to_string(file_name).unwrap().lines() {
-            let palette_one = u8::from_str_radix(&line[0..2], 16).unwrap();
-            let palette_two = u8::from_str_radix(&line[2..4], 16).unwrap();
-            let palette_three = u8::from_str_radix(&line[4..6], 16).unwrap();
-            palette_vec.push(((palette_one), (palette_two), (palette_three)));
+        for line in std::fs::read_to_string(file_name)?.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let channel = |range: std::ops::Range<usize>| {
+                line.get(range)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| invalid(format!("invalid colour '{}'", line)))
+            };
+            palette_vec.push((channel(0..2)?, channel(2..4)?, channel(4..6)?));
         }
-        if palette_vec.len() > 64 {
-            panic!("Palette file is too big");
+        if palette_vec.is_empty() || palette_vec.len() > 64 {
+            return Err(invalid(format!(
+                "expected up to 64 colours but found {}",
+                palette_vec.len()
+            )));
         }
-        palette_vec
-    }
-
-    fn get_background_palette(
-        ppu: &PPU,
-        attribute_table: &[u8],
-        row: usize,
-        col: usize,
-    ) -> Vec<u8> {
-        // https://www.nesdev.org/wiki/PPU_attribute_tables
-        let attribute_table_index = row / 4 * 8 + col / 4;
-        // Colour palette that exists in nametable..
-        let attribute_byte = attribute_table[attribute_table_index];
-        let palette_index = match (col % 4 / 2, row % 4 / 2) {
-            (0, 0) => attribute_byte & 0b11,
-            (1, 0) => (attribute_byte >> 2) & 0b11,
-            (0, 1) => (attribute_byte >> 4) & 0b11,
-            (1, 1) => (attribute_byte >> 6) & 0b11,
-            (_, _) => panic!("Invalid index value fetching background palette"),
-        };
-        let start: usize = 1 + (palette_index as usize) * 4;
-        vec![
-            ppu.palette_table[0],
-            ppu.palette_table[start],
-            ppu.palette_table[start + 1],
-            ppu.palette_table[start + 2],
-        ]
-    }
-
-    fn get_sprite_palette(ppu: &PPU, palette_index: u8) -> Vec<u8> {
-        let start = (palette_index * 4 + 0x11) as usize;
-        vec![
-            0,
-            ppu.palette_table[start],
-            ppu.palette_table[start + 1],
-            ppu.palette_table[start + 2],
-        ]
+        // Shorter palettes leave out the unused blacks at the end.
+        palette_vec.resize(64, (0, 0, 0));
+        Ok(palette_vec)
     }
 
     pub fn set_pixel(&mut self, x_pos: usize, y_pos: usize, colour: (u8, u8, u8)) {
@@ -92,188 +121,28 @@ impl Frame {
         }
     }
 
-    fn render_nametable(
-        ppu: &PPU,
-        frame: &mut Frame,
-        nametable: &[u8],
-        viewport: Rectangle,
-        palette: Vec<(u8, u8, u8)>,
-        s_x: isize,
-        s_y: isize,
-    ) {
-        let bank = ppu.reg_controller.background_pattern_table_address();
-        let attribute_table = &nametable[0x3C0..0x400];
-
-        for i in 0..0x3C0 {
-            let col = i % 32;
-            let row = i / 32;
-            let tile_index = nametable[i] as u16;
-            let tile = &ppu.chr_rom
-                [(bank + tile_index * 16) as usize..=(bank + tile_index * 16 + 15) as usize];
-            let bg_palette = Self::get_background_palette(ppu, attribute_table, row, col);
-
-            for y in 0..=7 {
-                let mut hh = tile[y];
-                let mut ll = tile[y + 8];
-                for x in (0..=7).rev() {
-                    let value = (0x01 & ll) << 1 | (0x01 & hh);
-                    hh >>= 1;
-                    ll >>= 1;
-                    let colour = match value {
-                        0 => palette[ppu.palette_table[0] as usize],
-                        1 => palette[bg_palette[1] as usize],
-                        2 => palette[bg_palette[2] as usize],
-                        3 => palette[bg_palette[3] as usize],
-                        _ => panic!("Couldn't set palette table value"),
-                    };
-                    let pixel_x = col * 8 + x;
-                    let pixel_y = row * 8 + y;
-
-                    if pixel_x >= viewport.x_1
-                        && pixel_x < viewport.x_2
-                        && pixel_y >= viewport.y_1
-                        && pixel_y < viewport.y_2
-                    {
-                        frame.set_pixel(
-                            (s_x + pixel_x as isize) as usize,
-                            (s_y + pixel_y as isize) as usize,
-                            colour,
-                        );
-                    }
-                }
-            }
-        }
-    }
-
-    pub fn render(ppu: &PPU, frame: &mut Frame, palette: Vec<(u8, u8, u8)>) {
-        let scx = ppu.reg_scroll.scx as usize;
-        let scy = ppu.reg_scroll.scy as usize;
-        let (primary_nametable, secondary_nametable) =
-            match (ppu.mirroring, ppu.reg_controller.nametable_address()) {
-                (MirroringType::Vertical, 0x2000)
-                | (MirroringType::Vertical, 0x2800)
-                | (MirroringType::Horizontal, 0x2000)
-                | (MirroringType::Horizontal, 0x2400) => {
-                    (&ppu.vram[0..0x400], &ppu.vram[0x400..0x800])
-                }
-                (MirroringType::Vertical, 0x2400)
-                | (MirroringType::Vertical, 0x2C00)
-                | (MirroringType::Horizontal, 0x2800)
-                | (MirroringType::Horizontal, 0x2C00) => {
-                    (&ppu.vram[0x400..0x800], &ppu.vram[0..0x400])
-                }
-                (_, _) => {
-                    panic!("Unsupported mirroring type for nametable rendering");
-                }
-            };
-        Self::render_nametable(
-            ppu,
-            frame,
-            primary_nametable,
-            Rectangle::new(scx, scy, 256, 240),
-            palette.clone(),
-            -(scx as isize),
-            -(scy as isize),
-        );
-
-        if scx > 0 {
-            Self::render_nametable(
-                ppu,
-                frame,
-                secondary_nametable,
-                Rectangle::new(0, 0, scx, 240),
-                palette.clone(),
-                (256 - scx) as isize,
-                0,
-            )
-        } else if scy > 0 {
-            Self::render_nametable(
-                ppu,
-                frame,
-                secondary_nametable,
-                Rectangle::new(0, 0, 256, scy),
-                palette.clone(),
-                0,
-                (240 - scy) as isize,
-            )
-        }
-
-        // // Iterate throguh OAM data
-        for j in (0..256).step_by(4).rev() {
-            /*
-             * BYTE 0 - Y POSITION TOP
-             * BYTE 1 = TILE INDEX
-             * BYTE 2 - ATTRIBUTES
-             * BYTE 3 - X POSITION LEFT
-             */
-            let tile_y = ppu.oam_data[j];
-            let tile_index = ppu.oam_data[j + 1] as u16;
-            let attributes = ppu.oam_data[j + 2];
-            let tile_x = ppu.oam_data[j + 3] as usize;
-            let palette_index = attributes & 0b11;
-            let sprite_palette = Self::get_sprite_palette(ppu, palette_index);
-            let bank: u16 = ppu.reg_controller.sprite_pattern_table_address();
-            let tile = &ppu.chr_rom
-                [(bank + tile_index * 16) as usize..=(bank + tile_index * 16 + 15) as usize];
-            for y in 0..=7 {
-                let mut hh = tile[y];
-                let mut ll = tile[y + 8];
-                'k: for x in (0..=7).rev() {
-                    let value = (0x01 & hh) << 1 | (0x01 & ll);
-                    hh >>= 1;
-                    ll >>= 1;
-                    // Assign colour of a given pixel
-                    let colour = match value {
-                        0b00 => continue 'k,
-                        0b01 => palette[sprite_palette[1] as usize],
-                        0b10 => palette[sprite_palette[2] as usize],
-                        0b11 => palette[sprite_palette[3] as usize],
-                        _ => panic!("Illegal palette value"),
-                    };
-                    // flip horizontal, flip vertical
-                    match (attributes >> 6 & 0x01, attributes >> 7 & 0x01) {
-                        (0, 0) => frame.set_pixel(
-                            (tile_x.wrapping_add(x)) as usize,
-                            (tile_y.wrapping_add(y as u8) as usize),
-                            colour,
-                        ),
-                        (1, 0) => frame.set_pixel(
-                            (tile_x.wrapping_add(7).wrapping_sub(x)) as usize,
-                            (tile_y.wrapping_add(y as u8)) as usize,
-                            colour,
-                        ),
-                        (0, 1) => frame.set_pixel(
-                            (tile_x.wrapping_add(x)) as usize,
-                            (tile_y.wrapping_add(7).wrapping_sub(y as u8)) as usize,
-                            colour,
-                        ),
-                        (1, 1) => frame.set_pixel(
-                            (tile_x.wrapping_add(7).wrapping_sub(x)) as usize,
-                            (tile_y.wrapping_add(7).wrapping_sub(y as u8)) as usize,
-                            colour,
-                        ),
-                        (_, _) => {}
-                    }
-                }
-            }
+    pub fn render(ppu: &PPU, frame: &mut FrameBuffer, palette: Vec<(u8, u8, u8)>) {
+        // The PPU has already drawn the picture dot by dot.
+        for (i, colour) in ppu.pixels.iter().enumerate() {
+            frame.set_pixel(i % WIDTH, i / WIDTH, palette[(colour & 0x3F) as usize]);
         }
     }
 
-    pub fn show_tile_bank(palette: Vec<(u8, u8, u8)>, chr_rom: &Vec<u8>, bank: usize) -> Frame {
+    pub fn show_tile_bank(palette: Vec<(u8, u8, u8)>, chr_rom: &[u8], bank: usize) -> FrameBuffer {
         if bank > 1 {
             panic!("Tile bank choice greater than 1");
         }
 
         let mut rng = rand::thread_rng();
         let mut palette_indexes: Vec<usize> = vec![];
-        for p in 0..4 {
+        for _ in 0..4 {
             palette_indexes.push(rng.gen_range(0..55));
         }
 
-        let mut frame = Frame::new();
+        let mut frame = FrameBuffer::new();
         let mut tile_y = 0;
         let mut tile_x = 0;
-        let tile_bank = (bank * 0x1000) as usize;
+        let tile_bank = bank * 0x1000;
         // Render limit for tiles on each row
         let tile_limit_per_row = WIDTH / 8;
         // Iterate over tiles in bank (256 total per bank)
@@ -315,3 +184,9 @@ impl Frame {
         frame
     }
 }
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,46 +1,88 @@
 use crate::cartridge::MirroringType;
-use reg_addr::PPUADDR;
+use crate::mapper::Mapper;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use background::Background;
+use sprites::Sprite;
 use reg_controller::PPUCTRL;
 use reg_mask::PPUMASK;
-use reg_scroll::PPUSCROLL;
 use reg_status::PPUSTATUS;
-use frame::Frame;
+use frame::{HEIGHT, WIDTH};
+use std::cell::RefCell;
+use std::rc::Rc;
 
-pub mod reg_addr;
+pub mod background;
 pub mod reg_controller;
 pub mod reg_mask;
-pub mod reg_scroll;
 pub mod reg_status;
+pub mod sprites;
 pub mod frame;
 
 pub struct PPU {
-    pub chr_rom: Vec<u8>,
     pub palette_table: [u8; 32],
-    pub vram: [u8; 2048],
-    pub mirroring: MirroringType,
+    // 2KB of nametable RAM in the console, plus the 2KB that four-screen
+    // cartridges add so that each nametable has its own.
+    pub vram: [u8; 4096],
+    pub mapper: Rc<RefCell<dyn Mapper>>,
+    /*
+     *   https://www.nesdev.org/wiki/PPU_scrolling
+     *
+     *   v and t are 15 bit VRAM addresses laid out as scroll positions:
+     *
+     *   yyy NN YYYYY XXXXX
+     *   ||| || ||||| +++++-- coarse X scroll
+     *   ||| || +++++-------- coarse Y scroll
+     *   ||| ++-------------- nametable select
+     *   +++----------------- fine Y scroll
+     *
+     *   v is the address being rendered (or accessed through 0x2007) and t
+     *   the top left of the screen, which 0x2000, 0x2005 and 0x2006 write to
+     *   and which is copied into v during rendering. x is the fine X scroll
+     *   and w the write toggle shared by 0x2005 and 0x2006.
+     */
     pub reg_v: u16,
     pub reg_t: u16,
     pub reg_x: u8,
     pub reg_w: bool,
-    pub reg_address: PPUADDR,
     pub reg_controller: PPUCTRL,
     pub reg_mask: PPUMASK,
     pub reg_status: PPUSTATUS,
-    pub reg_scroll: PPUSCROLL,
 
     pub nmi_interrupt: Option<u8>, 
+    // Set when the visible part of a frame has finished, at the start of
+    // vertical blank.
+    pub frame_complete: bool,
     internal_data_buffer: u8,
     
     pub oam_data: [u8; 256],
     pub oam_address: u8,
 
+    // Colour (an index into the system palette) of each pixel drawn so far
+    // this frame.
+    pub pixels: Vec<u8>,
+    background: Background,
+    // Sprites found on the current line by evaluation, which are drawn on
+    // the line after.
+    sprites: Vec<Sprite>,
+    // The PPU only draws 8 sprites a line, which games work around by
+    // flickering them. Turning the limit off draws every sprite instead.
+    pub sprite_limit: bool,
+
     scanline: u16,
     cycles: usize,
+    odd_frame: bool,
 }
 
 const FRAME_SCANLINE_LIMIT: u16 = 262;
+const VISIBLE_SCANLINE_LIMIT: u16 = 240;
 const VBLANK_SCANLINE_LIMIT: u16 = 241;
 const SCANLINE_PPU_CYCLE_LIMIT: usize = 341;
+const PRE_RENDER_SCANLINE: u16 = 261;
+// Sprites on the next line are found by the end of dot 256, and their
+// patterns fetched over dots 257 - 320.
+const SPRITE_EVALUATION_CYCLE: usize = 257;
+const SPRITE_FETCH_CYCLES: std::ops::RangeInclusive<usize> = 257..=320;
+// Empty sprite slots fetch the patterns of tile 0xFF.
+const EMPTY_SPRITE_TILE: u16 = 0xFF;
 
 impl PPU {
     /*
@@ -51,62 +93,337 @@ impl PPU {
      *      @ 0x2000.
      *   4. CPU reads data register 0x2007, prompting the PPU to return the internal buffer data.
      */
-    pub fn new(chr_rom: Vec<u8>, mirroring: MirroringType) -> Self {
+    pub fn new(mapper: Rc<RefCell<dyn Mapper>>) -> Self {
         PPU {
-            chr_rom,
             palette_table: [0; 32],
-            vram: [0; 2048],
-            mirroring,
+            vram: [0; 4096],
+            mapper,
             reg_v: 0,
             reg_t: 0,
             reg_x: 0,
-            reg_w: true,
-            reg_address: PPUADDR::new(),
+            reg_w: false,
             reg_controller: PPUCTRL::new(),
             reg_status: PPUSTATUS::new(),
             reg_mask: PPUMASK::new(),
-            reg_scroll: PPUSCROLL::new(),
             internal_data_buffer: 0,
             oam_data: [0; 256],
             oam_address: 0,
+            pixels: vec![0; WIDTH * HEIGHT],
+            background: Background::new(),
+            sprites: Vec::new(),
+            sprite_limit: true,
             scanline: 0,
             cycles: 0,
+            odd_frame: false,
             nmi_interrupt: None,
+            frame_complete: false,
         }
     }
 
     pub fn tick(&mut self, cycles: u8) -> bool {
-        self.cycles += cycles as usize;     
-        if self.cycles >= SCANLINE_PPU_CYCLE_LIMIT {
-            if self.poll_sprite_zero_hit(self.cycles) {
-                self.reg_status.set_sprite_zero_hit(true);
-            }
-            // don't set to zero -> eating cycles!
-            self.cycles = self.cycles - SCANLINE_PPU_CYCLE_LIMIT;
-            self.scanline += 1;
-            if self.scanline == VBLANK_SCANLINE_LIMIT {
+        let mut frame_finished = false;
+        for _ in 0..cycles {
+            frame_finished |= self.step_dot();
+        }
+        frame_finished
+    }
+
+    // Run a single dot (PPU cycle). Returns true once the pre-render
+    // scanline has finished and the next frame starts.
+    fn step_dot(&mut self) -> bool {
+        let visible = self.scanline < VISIBLE_SCANLINE_LIMIT;
+        let pre_render = self.scanline == PRE_RENDER_SCANLINE;
+        if (visible || pre_render) && self.rendering_enabled() {
+            self.fetch_background();
+            if self.cycles == SPRITE_EVALUATION_CYCLE {
+                self.evaluate_sprites();
+            }
+            if SPRITE_FETCH_CYCLES.contains(&self.cycles) {
+                self.fetch_sprites();
+            }
+        }
+        if visible && (1..=WIDTH).contains(&self.cycles) {
+            self.draw_pixel();
+        }
+        match (self.scanline, self.cycles) {
+            (VBLANK_SCANLINE_LIMIT, 1) => {
                 self.reg_status.set_vblank_started(true);
-                self.reg_status.set_sprite_zero_hit(false);
+                self.frame_complete = true;
                 if self.reg_controller.generate_nmi() {
                     self.nmi_interrupt = Some(1);
                 }
-
             }
-            if self.scanline >= FRAME_SCANLINE_LIMIT {
-                self.scanline = 0;
+            (PRE_RENDER_SCANLINE, 1) => {
                 self.nmi_interrupt = None;
                 self.reg_status.set_sprite_zero_hit(false);
+                self.reg_status.set_sprite_overflow(false);
                 self.reg_status.reset_vblank();
-                return true
             }
-        } 
-        false
+            _ => {}
+        }
+
+        self.cycles += 1;
+        // With rendering enabled, odd frames skip the last dot of the
+        // pre-render line.
+        if pre_render
+            && self.odd_frame
+            && self.cycles == SCANLINE_PPU_CYCLE_LIMIT - 1
+            && self.rendering_enabled()
+        {
+            self.cycles += 1;
+        }
+        if self.cycles < SCANLINE_PPU_CYCLE_LIMIT {
+            return false;
+        }
+        self.cycles = 0;
+        self.scanline += 1;
+        if self.scanline < FRAME_SCANLINE_LIMIT {
+            return false;
+        }
+        self.scanline = 0;
+        self.odd_frame = !self.odd_frame;
+        true
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.reg_mask.is_background_enabled() || self.reg_mask.is_sprite_enabled()
+    }
+
+    /*
+     *   Each tile takes 8 dots to fetch: the nametable byte, the attribute
+     *   byte and the low and high pattern bytes, two dots apiece. Tiles are
+     *   fetched over dots 1 - 256 for the current line and 321 - 336 for the
+     *   first two tiles of the next, with coarse X moving on after each one.
+     */
+    fn fetch_background(&mut self) {
+        let dot = self.cycles;
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.background.shift();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.background.reload();
+                    let address = 0x2000 | (self.reg_v & 0x0FFF);
+                    self.background.next_tile = self.read_nametable(address);
+                }
+                2 => {
+                    let v = self.reg_v;
+                    let address = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    // Each attribute byte covers 4x4 tiles, two bits for
+                    // each 2x2 quadrant.
+                    let shift = ((v >> 4) & 0b100) | (v & 0b10);
+                    self.background.next_attribute = (self.read_nametable(address) >> shift) & 0b11;
+                }
+                4 => {
+                    let address = self.background_pattern_address();
+                    self.background.next_pattern_low = self.fetch_pattern(address);
+                }
+                6 => {
+                    let address = self.background_pattern_address() + 8;
+                    self.background.next_pattern_high = self.fetch_pattern(address);
+                }
+                7 => self.increment_coarse_x(),
+                _ => {}
+            }
+        }
+        match dot {
+            256 => self.increment_y(),
+            257 => self.copy_horizontal_scroll(),
+            280..=304 if self.scanline == PRE_RENDER_SCANLINE => self.copy_vertical_scroll(),
+            _ => {}
+        }
+    }
+
+    fn background_pattern_address(&self) -> u16 {
+        let fine_y = self.reg_v >> 12;
+        self.reg_controller.background_pattern_table_address()
+            + self.background.next_tile as u16 * 16
+            + fine_y
+    }
+
+    // Nothing is evaluated on the pre-render line, so no sprites are drawn
+    // on the first visible line.
+    fn evaluate_sprites(&mut self) {
+        if self.scanline == PRE_RENDER_SCANLINE {
+            self.sprites.clear();
+            return;
+        }
+        let overflow = sprites::evaluate(
+            &self.oam_data,
+            self.scanline,
+            self.reg_controller.sprite_size() as u16,
+            self.sprite_limit,
+            &mut self.sprites,
+        );
+        if overflow {
+            self.reg_status.set_sprite_overflow(true);
+        }
+    }
+
+    // Each of the 8 sprite slots takes 8 dots to fetch, the patterns being
+    // read on the fifth and seventh. Slots without a sprite fetch tile 0xFF
+    // and throw the result away. With the sprite limit off, the sprites
+    // past the eighth are fetched along with the last slot.
+    fn fetch_sprites(&mut self) {
+        let slot = (self.cycles - 257) / 8;
+        let high = match (self.cycles - 257) % 8 {
+            4 => false,
+            6 => true,
+            _ => return,
+        };
+        let slots = match slot == sprites::SPRITE_LIMIT - 1 {
+            true => slot..self.sprites.len().max(sprites::SPRITE_LIMIT),
+            false => slot..slot + 1,
+        };
+        for slot in slots {
+            match self.sprites.get(slot).copied() {
+                Some(sprite) => {
+                    let address = self.sprite_pattern_address(&sprite) + if high { 8 } else { 0 };
+                    let mut pattern = self.fetch_pattern(address);
+                    if sprite.flip_horizontal() {
+                        pattern = pattern.reverse_bits();
+                    }
+                    match high {
+                        true => self.sprites[slot].pattern_high = pattern,
+                        false => self.sprites[slot].pattern_low = pattern,
+                    }
+                }
+                None => {
+                    let address = self.sprite_tile_address(EMPTY_SPRITE_TILE, 0);
+                    self.fetch_pattern(address + if high { 8 } else { 0 });
+                }
+            }
+        }
+    }
+
+    // Address of the row of a sprite's pattern on the next line. Flipping
+    // an 8x16 sprite vertically also swaps its two tiles.
+    fn sprite_pattern_address(&self, sprite: &Sprite) -> u16 {
+        self.sprite_tile_address(sprite.tile as u16, sprite.row as u16)
+    }
+
+    /*
+     *   8x8 sprites take their tiles from the pattern table picked by
+     *   PPUCTRL. 8x16 sprites ignore it and use bit 0 of the tile index
+     *   instead, the rest of the index giving the top tile of a pair:
+     *
+     *   76543210
+     *   ||||||||
+     *   |||||||+- Pattern table (0: 0x0000; 1: 0x1000)
+     *   +++++++-- Tile number of the top half (the bottom is the next tile)
+     */
+    fn sprite_tile_address(&self, tile: u16, row: u16) -> u16 {
+        match self.reg_controller.sprite_size() {
+            16 => {
+                let table = (tile & 0b1) * 0x1000;
+                let tile = (tile & 0xFE) + row / 8;
+                table + tile * 16 + row % 8
+            }
+            // A row from an 8x16 sprite if the size changed since evaluation.
+            _ => self.reg_controller.sprite_pattern_table_address() + tile * 16 + row % 8,
+        }
+    }
+
+    // Pattern table reads made while rendering. The mapper sees each
+    // address on the bus, so it can follow PPU A12.
+    fn fetch_pattern(&mut self, address: u16) -> u8 {
+        let mut mapper = self.mapper.borrow_mut();
+        mapper.ppu_bus_address(address);
+        mapper.ppu_read(address)
+    }
+
+    fn read_nametable(&self, address: u16) -> u8 {
+        self.vram[self.mirror_vram(address) as usize]
+    }
+
+    fn draw_pixel(&mut self) {
+        let x = self.cycles - 1;
+        let show_background = self.reg_mask.is_background_enabled()
+            && (x >= 8 || self.reg_mask.is_background_leftmost_enabled());
+        let show_sprites = self.reg_mask.is_sprite_enabled()
+            && (x >= 8 || self.reg_mask.is_sprite_leftmost_enabled());
+        let background = match show_background {
+            true => self.background.pixel(self.reg_x),
+            false => 0,
+        };
+        // Only the first sprite in OAM with an opaque pixel here counts. If
+        // it's behind the background it hides any later sprites in front,
+        // which SMB3 uses to have items rise out of blocks.
+        let sprite = match show_sprites {
+            true => self.sprites.iter().find(|sprite| sprite.pixel(x) != 0),
+            false => None,
+        };
+        // Sprite palettes follow the background ones, at 0x3F10. Where both
+        // are transparent the backdrop colour at 0x3F00 shows through.
+        let background_opaque = background & 0b11 != 0;
+        // Sprite zero hits where its own pixel and the background are both
+        // opaque, whether or not another sprite is drawn over it. Clipped
+        // pixels don't count, and neither does the last column.
+        let sprite_zero_hit = show_background
+            && show_sprites
+            && background_opaque
+            && x != WIDTH - 1
+            && self
+                .sprites
+                .first()
+                .is_some_and(|sprite| sprite.index == 0 && sprite.pixel(x) != 0);
+        if sprite_zero_hit {
+            self.reg_status.set_sprite_zero_hit(true);
+        }
+        let entry = match sprite {
+            Some(sprite) if !background_opaque || !sprite.behind_background() => {
+                0x10 | (sprite.palette() << 2 | sprite.pixel(x)) as usize
+            }
+            _ if background_opaque => background as usize,
+            _ => 0,
+        };
+        let mut colour = self.palette_table[entry];
+        if self.reg_mask.is_greyscale_enabled() {
+            colour &= 0x30;
+        }
+        self.pixels[self.scanline as usize * WIDTH + x] = colour;
+    }
+
+    // Move v to the next tile across, wrapping into the horizontally
+    // adjacent nametable.
+    fn increment_coarse_x(&mut self) {
+        if self.reg_v & 0x001F == 31 {
+            self.reg_v &= !0x001F;
+            self.reg_v ^= 0x0400;
+        } else {
+            self.reg_v += 1;
+        }
+    }
+
+    // Move v down a line. Coarse Y wraps into the vertically adjacent
+    // nametable after row 29, the last row of tiles. Rows 30 and 31 hold
+    // attributes, and if scrolled into they wrap without switching.
+    fn increment_y(&mut self) {
+        if self.reg_v & 0x7000 != 0x7000 {
+            self.reg_v += 0x1000;
+            return;
+        }
+        self.reg_v &= !0x7000;
+        let coarse_y = match (self.reg_v & 0x03E0) >> 5 {
+            29 => {
+                self.reg_v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            coarse_y => coarse_y + 1,
+        };
+        self.reg_v = (self.reg_v & !0x03E0) | (coarse_y << 5);
     }
 
-    pub fn poll_sprite_zero_hit(&self, cycle: usize) -> bool {
-        let x = self.oam_data[3] as usize;
-        let y = self.oam_data[0] as usize;
-        (y == self.scanline as usize) && x <= cycle && self.reg_mask.is_sprite_enabled()
+    fn copy_horizontal_scroll(&mut self) {
+        self.reg_v = (self.reg_v & !0x041F) | (self.reg_t & 0x041F);
+    }
+
+    fn copy_vertical_scroll(&mut self) {
+        self.reg_v = (self.reg_v & !0x7BE0) | (self.reg_t & 0x7BE0);
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
     }
 
     pub fn poll_for_nmi_interrupt(&mut self) -> Option<u8> {
@@ -133,13 +450,22 @@ impl PPU {
         self.oam_data[self.oam_address as usize]
     }
     
+    // The first write sets the high six bits of t (clearing bit 14), the
+    // second the low byte, after which t is copied into v.
     pub fn write_to_reg_addr(&mut self, value: u8) {
-        self.reg_address.update(value);
+        if !self.reg_w {
+            self.reg_t = (self.reg_t & 0x00FF) | ((value as u16 & 0x3F) << 8);
+        } else {
+            self.reg_t = (self.reg_t & 0xFF00) | value as u16;
+            self.reg_v = self.reg_t;
+        }
+        self.reg_w = !self.reg_w;
     }
     
     pub fn write_to_reg_ctrl(&mut self, value: u8) {
         let previous_nmi_status = self.reg_controller.generate_nmi();
         self.reg_controller.update(value);
+        self.reg_t = (self.reg_t & !0x0C00) | ((value as u16 & 0b11) << 10);
         // If there wasn't an NMI before, there is an NMI now from reading NMI bit in control
         // register, and the PPU is in VBLANK... 
         if ! previous_nmi_status && self.reg_controller.generate_nmi() && self.reg_status.in_vblank() {
@@ -151,34 +477,54 @@ impl PPU {
         self.reg_mask.update(value);
     }
     
+    // The first write sets coarse and fine X, the second coarse and fine Y.
     pub fn write_to_reg_scroll(&mut self, value: u8) {
-        self.reg_scroll.write_scroll(value);
+        if !self.reg_w {
+            self.reg_t = (self.reg_t & !0x001F) | (value as u16 >> 3);
+            self.reg_x = value & 0b111;
+        } else {
+            self.reg_t = (self.reg_t & !0x73E0)
+                | ((value as u16 & 0b111) << 12)
+                | ((value as u16 & 0xF8) << 2);
+        }
+        self.reg_w = !self.reg_w;
     }
     
     pub fn read_status(&mut self) -> u8 {
         let current_status = self.reg_status.bits();
         // VBLANK is cleared after reading 0x2002
         self.reg_status.reset_vblank();
-        self.reg_address.reset();
-        self.reg_scroll.reset_scroll();
+        self.reg_w = false;
         current_status
     }
 
     pub fn increment_vram_address(&mut self) {
-        if !self.reg_controller.contains(PPUCTRL::VRAM_ADDR_INCREMENT) {
-            self.reg_address.increment(1);
+        let rendering_line =
+            self.scanline < VISIBLE_SCANLINE_LIMIT || self.scanline == PRE_RENDER_SCANLINE;
+        if rendering_line && self.rendering_enabled() {
+            // While rendering, accessing 0x2007 bumps both scroll counters.
+            self.increment_coarse_x();
+            self.increment_y();
+        } else if !self.reg_controller.contains(PPUCTRL::VRAM_ADDR_INCREMENT) {
+            self.reg_v = (self.reg_v + 1) & 0x7FFF;
         } else {
-            self.reg_address.increment(32);
+            self.reg_v = (self.reg_v + 32) & 0x7FFF;
         }
     }
 
+    // Only 14 bits of v reach the address bus.
+    fn vram_address(&self) -> u16 {
+        self.reg_v & 0x3FFF
+    }
+
     pub fn write_data(&mut self, value: u8) {
-        let address = self.reg_address.get();
+        let address = self.vram_address();
         match address {
             0..=0x1FFF => {
-                println!("Illegal attempt to write to CHR ROM address space: {}", address);
+                self.mapper.borrow_mut().ppu_write(address, value);
             }
-            0x2000..=0x2FFF => {
+            // 0x3000 - 0x3EFF mirrors the nametables.
+            0x2000..=0x3EFF => {
                 self.vram[self.mirror_vram(address) as usize] = value;
             }
             // Mirror addresses of palette table values 
@@ -198,20 +544,19 @@ impl PPU {
     }
 
     pub fn read_data(&mut self) -> u8 {
-        let address = self.reg_address.get();
+        let address = self.vram_address();
         self.increment_vram_address();
         match address {
             0..=0x1FFF => {
                 let buffer_data = self.internal_data_buffer;
-                self.internal_data_buffer = self.chr_rom[address as usize];
+                self.internal_data_buffer = self.mapper.borrow().ppu_read(address);
                 buffer_data
             }
-            0x2000..=0x2FFF => {
+            0x2000..=0x3EFF => {
                 let buffer_data = self.internal_data_buffer;
                 self.internal_data_buffer = self.vram[self.mirror_vram(address) as usize];
                 buffer_data
             }
-            0x3000..=0x3EFF => panic!("Illegal memory space access at {}", address),
             0x3F10 | 0x3F14 | 0x3F18 | 0x3F1C => {
                 let mirror_down = address - 0x10;
                 self.palette_table[(mirror_down - 0x3F00) as usize]
@@ -221,6 +566,12 @@ impl PPU {
         }
     }
 
+    // Mappers such as MMC1 can change the nametable arrangement at runtime,
+    // so the current mirroring is always taken from the mapper.
+    pub fn mirroring(&self) -> MirroringType {
+        self.mapper.borrow().mirroring()
+    }
+
     pub fn mirror_vram(&self, address: u16) -> u16 {
         // Mirror down to addressable VRAM space
         let mirror_down = address & 0x2FFF;
@@ -229,14 +580,327 @@ impl PPU {
         // Get corresponding nametable of given address
         let nametable = vram_position / 0x400;
 
-        match (&self.mirroring, nametable) {
+        match (self.mirroring(), nametable) {
             (MirroringType::Vertical, 2)
             | (MirroringType::Vertical, 3)
             | (MirroringType::Horizontal, 3) => vram_position - 0x800,
             (MirroringType::Horizontal, 1) | (MirroringType::Horizontal, 2) => {
                 vram_position - 0x400
             }
+            (MirroringType::SingleScreenLower, _) => vram_position & 0x3FF,
+            (MirroringType::SingleScreenUpper, _) => 0x400 | (vram_position & 0x3FF),
             _ => vram_position,
         }
     }
 }
+
+impl Snapshot for PPU {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.palette_table);
+        state.write_bytes(&self.vram);
+        state.write_u16(self.reg_v);
+        state.write_u16(self.reg_t);
+        state.write_u8(self.reg_x);
+        state.write_bool(self.reg_w);
+        state.write_u8(self.reg_controller.bits());
+        state.write_u8(self.reg_mask.bits());
+        state.write_u8(self.reg_status.bits());
+        state.write_bool(self.nmi_interrupt.is_some());
+        state.write_u8(self.nmi_interrupt.unwrap_or(0));
+        state.write_bool(self.frame_complete);
+        state.write_u8(self.internal_data_buffer);
+        state.write_bytes(&self.oam_data);
+        state.write_u8(self.oam_address);
+        self.background.save_state(state);
+        state.write_u8(self.sprites.len() as u8);
+        for sprite in self.sprites.iter() {
+            sprite.save_state(state);
+        }
+        state.write_u16(self.scanline);
+        state.write_usize(self.cycles);
+        state.write_bool(self.odd_frame);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes(&mut self.palette_table)?;
+        state.read_bytes(&mut self.vram)?;
+        self.reg_v = state.read_u16()?;
+        self.reg_t = state.read_u16()?;
+        self.reg_x = state.read_u8()?;
+        self.reg_w = state.read_bool()?;
+        self.reg_controller = PPUCTRL::from_bits_retain(state.read_u8()?);
+        self.reg_mask = PPUMASK::from_bits_retain(state.read_u8()?);
+        self.reg_status = PPUSTATUS::from_bits_retain(state.read_u8()?);
+        let nmi_pending = state.read_bool()?;
+        let nmi = state.read_u8()?;
+        self.nmi_interrupt = if nmi_pending { Some(nmi) } else { None };
+        self.frame_complete = state.read_bool()?;
+        self.internal_data_buffer = state.read_u8()?;
+        state.read_bytes(&mut self.oam_data)?;
+        self.oam_address = state.read_u8()?;
+        self.background.load_state(state)?;
+        let sprite_count = state.read_u8()?;
+        if sprite_count > 64 {
+            return Err(StateError::Invalid("more than 64 sprites on a line"));
+        }
+        self.sprites.clear();
+        for _ in 0..sprite_count {
+            let mut sprite = Sprite::new();
+            sprite.load_state(state)?;
+            self.sprites.push(sprite);
+        }
+        self.scanline = state.read_u16()?;
+        self.cycles = state.read_usize()?;
+        self.odd_frame = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::ChrMemory;
+    use crate::mapper::nrom::NROM;
+
+    fn test_ppu(mirroring: MirroringType) -> PPU {
+        let mapper = NROM::new(vec![0; 0x4000], ChrMemory::ram(0x2000), 0, mirroring);
+        PPU::new(Rc::new(RefCell::new(mapper)))
+    }
+
+    fn set_address(ppu: &mut PPU, address: u16) {
+        ppu.write_to_reg_addr((address >> 8) as u8);
+        ppu.write_to_reg_addr(address as u8);
+    }
+
+    fn write_vram(ppu: &mut PPU, address: u16, value: u8) {
+        set_address(ppu, address);
+        ppu.write_data(value);
+    }
+
+    // Reads below the palette come through a buffer, a read behind.
+    fn read_vram(ppu: &mut PPU, address: u16) -> u8 {
+        set_address(ppu, address);
+        ppu.read_data();
+        ppu.read_data()
+    }
+
+    // The value read back from each nametable after writing 1 - 4 to them
+    // in turn.
+    fn nametables(mirroring: MirroringType) -> [u8; 4] {
+        let mut ppu = test_ppu(mirroring);
+        for nametable in 0..4 {
+            write_vram(&mut ppu, 0x2000 + nametable * 0x400 + 5, nametable as u8 + 1);
+        }
+        [0x2005, 0x2405, 0x2805, 0x2C05].map(|address| read_vram(&mut ppu, address))
+    }
+
+    #[test]
+    fn mirrors_nametables() {
+        assert_eq!(nametables(MirroringType::Vertical), [3, 4, 3, 4]);
+        assert_eq!(nametables(MirroringType::Horizontal), [2, 2, 4, 4]);
+        assert_eq!(nametables(MirroringType::SingleScreenLower), [4, 4, 4, 4]);
+        assert_eq!(nametables(MirroringType::SingleScreenUpper), [4, 4, 4, 4]);
+        assert_eq!(nametables(MirroringType::FourScreen), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn four_screen_uses_separate_ram() {
+        let mut ppu = test_ppu(MirroringType::FourScreen);
+        write_vram(&mut ppu, 0x2800, 0xAA);
+        write_vram(&mut ppu, 0x2EFE, 0xBB);
+        assert_eq!(read_vram(&mut ppu, 0x2000), 0);
+        assert_eq!(read_vram(&mut ppu, 0x2800), 0xAA);
+        // 0x3000 - 0x3EFF mirrors the nametables.
+        assert_eq!(read_vram(&mut ppu, 0x3EFE), 0xBB);
+    }
+
+    #[test]
+    fn ctrl_write_sets_nametable_in_t() {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        ppu.reg_t = 0x7FFF;
+        ppu.write_to_reg_ctrl(0b10);
+        assert_eq!(ppu.reg_t, 0x7BFF);
+        ppu.write_to_reg_ctrl(0b01);
+        assert_eq!(ppu.reg_t, 0x77FF);
+    }
+
+    #[test]
+    fn scroll_writes_set_t_and_fine_x() {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        ppu.write_to_reg_ctrl(0b11);
+        // X = 0x7D: coarse X 15, fine X 5.
+        ppu.write_to_reg_scroll(0x7D);
+        assert_eq!(ppu.reg_t, 0x0C0F);
+        assert_eq!(ppu.reg_x, 5);
+        assert!(ppu.reg_w);
+        // Y = 0x5E: coarse Y 11, fine Y 6.
+        ppu.write_to_reg_scroll(0x5E);
+        assert_eq!(ppu.reg_t, 0x6C0F | 11 << 5);
+        assert!(!ppu.reg_w);
+        // Neither write touches v.
+        assert_eq!(ppu.reg_v, 0);
+    }
+
+    #[test]
+    fn addr_writes_set_t_then_copy_to_v() {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        ppu.reg_t = 0x7FFF;
+        // The high byte write clears bit 14 of t.
+        ppu.write_to_reg_addr(0xFF);
+        assert_eq!(ppu.reg_t, 0x3FFF);
+        assert_eq!(ppu.reg_v, 0);
+        ppu.write_to_reg_addr(0x21);
+        assert_eq!(ppu.reg_t, 0x3F21);
+        assert_eq!(ppu.reg_v, 0x3F21);
+        assert!(!ppu.reg_w);
+    }
+
+    #[test]
+    fn status_read_resets_write_toggle() {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        ppu.write_to_reg_scroll(0x08);
+        ppu.read_status();
+        // Taken as another first write, so it sets X again.
+        ppu.write_to_reg_scroll(0x10);
+        assert_eq!(ppu.reg_t, 0x0002);
+        assert!(ppu.reg_w);
+    }
+
+    #[test]
+    fn coarse_x_wraps_into_next_nametable() {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        ppu.reg_v = 0x001E;
+        ppu.increment_coarse_x();
+        assert_eq!(ppu.reg_v, 0x001F);
+        ppu.increment_coarse_x();
+        assert_eq!(ppu.reg_v, 0x0400);
+        ppu.reg_v = 0x041F;
+        ppu.increment_coarse_x();
+        assert_eq!(ppu.reg_v, 0x0000);
+    }
+
+    #[test]
+    fn y_increments_fine_then_coarse() {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        ppu.reg_v = 0x6000 | 3 << 5;
+        ppu.increment_y();
+        assert_eq!(ppu.reg_v, 0x7000 | 3 << 5);
+        ppu.increment_y();
+        assert_eq!(ppu.reg_v, 4 << 5);
+    }
+
+    #[test]
+    fn coarse_y_wraps_after_row_29() {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        ppu.reg_v = 0x7000 | 29 << 5;
+        ppu.increment_y();
+        assert_eq!(ppu.reg_v, 0x0800);
+        ppu.reg_v = 0x7800 | 29 << 5;
+        ppu.increment_y();
+        assert_eq!(ppu.reg_v, 0x0000);
+        // Scrolled into the attribute rows, it wraps in place.
+        ppu.reg_v = 0x7000 | 31 << 5;
+        ppu.increment_y();
+        assert_eq!(ppu.reg_v, 0x0000);
+    }
+
+    #[test]
+    fn sprite_tile_address_8x8() {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        assert_eq!(ppu.sprite_tile_address(0x12, 3), 0x0123);
+        // PPUCTRL bit 3 picks the table.
+        ppu.write_to_reg_ctrl(0b1000);
+        assert_eq!(ppu.sprite_tile_address(0x12, 3), 0x1123);
+    }
+
+    #[test]
+    fn sprite_tile_address_8x16() {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        // The pattern table bit of PPUCTRL is ignored.
+        ppu.write_to_reg_ctrl(0b0010_1000);
+        assert_eq!(ppu.sprite_tile_address(0x12, 3), 0x0123);
+        assert_eq!(ppu.sprite_tile_address(0x12, 11), 0x0133);
+        // Bit 0 of the tile picks the table instead.
+        assert_eq!(ppu.sprite_tile_address(0x13, 3), 0x1123);
+        assert_eq!(ppu.sprite_tile_address(0x13, 15), 0x1137);
+    }
+
+    // A PPU drawing column x of the first line over a background that is
+    // either opaque (colour 1) or transparent, with the given sprite drawn
+    // in colour 1 of its palette.
+    fn drawing_ppu(x: usize, background_opaque: bool, sprite: Sprite) -> PPU {
+        let mut ppu = test_ppu(MirroringType::Vertical);
+        // Background, sprites and both in the leftmost column.
+        ppu.write_to_reg_mask(0b0001_1110);
+        ppu.palette_table[0x00] = 0x0F;
+        ppu.palette_table[0x01] = 0x11;
+        ppu.palette_table[0x11] = 0x21;
+        ppu.background.next_pattern_low = if background_opaque { 0xFF } else { 0x00 };
+        ppu.background.reload();
+        for _ in 0..8 {
+            ppu.background.shift();
+        }
+        ppu.sprites.push(Sprite {
+            x: x as u8,
+            pattern_low: 0xFF,
+            ..sprite
+        });
+        ppu.scanline = 0;
+        ppu.cycles = x + 1;
+        ppu
+    }
+
+    fn draw(x: usize, background_opaque: bool, sprite: Sprite) -> (u8, bool) {
+        let mut ppu = drawing_ppu(x, background_opaque, sprite);
+        ppu.draw_pixel();
+        (
+            ppu.pixels[x],
+            ppu.reg_status.contains(PPUSTATUS::SPRITE_ZERO_HIT),
+        )
+    }
+
+    #[test]
+    fn sprite_priority() {
+        let front = Sprite {
+            index: 1,
+            ..Sprite::new()
+        };
+        let behind = Sprite {
+            index: 1,
+            attributes: 0b0010_0000,
+            ..Sprite::new()
+        };
+        assert_eq!(draw(10, true, front).0, 0x21);
+        assert_eq!(draw(10, false, front).0, 0x21);
+        assert_eq!(draw(10, true, behind).0, 0x11);
+        assert_eq!(draw(10, false, behind).0, 0x21);
+    }
+
+    #[test]
+    fn sprite_zero_hit() {
+        let zero = Sprite::new();
+        let other = Sprite {
+            index: 1,
+            ..Sprite::new()
+        };
+        let behind = Sprite {
+            attributes: 0b0010_0000,
+            ..Sprite::new()
+        };
+        assert!(draw(10, true, zero).1);
+        // Still a hit when drawn behind the background.
+        assert!(draw(10, true, behind).1);
+        assert!(!draw(10, false, zero).1);
+        assert!(!draw(10, true, other).1);
+        // Never on the last column.
+        assert!(!draw(WIDTH - 1, true, zero).1);
+    }
+
+    #[test]
+    fn sprite_zero_hit_respects_left_clipping() {
+        let mut ppu = drawing_ppu(3, true, Sprite::new());
+        ppu.write_to_reg_mask(0b0001_1000);
+        ppu.draw_pixel();
+        assert!(!ppu.reg_status.contains(PPUSTATUS::SPRITE_ZERO_HIT));
+    }
+}
@@ -1,4 +1,3 @@
-use bitflags::Flags;
 /*
 https://www.nesdev.org/wiki/PPU_registers#Controller_($2000)_%3E_write
 
@@ -83,3 +82,9 @@ impl PPUCTRL {
         self.contains(PPUCTRL::GENERATE_NMI)
     } 
 }
+
+impl Default for PPUCTRL {
+    fn default() -> Self {
+        Self::new()
+    }
+}
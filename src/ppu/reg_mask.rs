@@ -1,4 +1,3 @@
-use bitflags::Flags;
 // 7  bit  0
 // ---- ----
 // BGRs bMmG
@@ -72,3 +71,9 @@ impl PPUMASK {
         *self = PPUMASK::from_bits_truncate(value);
     }
 }
+
+impl Default for PPUMASK {
+    fn default() -> Self {
+        Self::new()
+    }
+}
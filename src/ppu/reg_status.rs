@@ -1,4 +1,3 @@
-use bitflags::Flags;
 // 7  bit  0
 // ---- ----
 // VSO. ....
@@ -52,3 +51,9 @@ impl PPUSTATUS {
     pub fn in_vblank(&self) -> bool {
         self.contains(PPUSTATUS::VBLANK_STARTED)}
 }
+
+impl Default for PPUSTATUS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
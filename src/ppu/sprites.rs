@@ -0,0 +1,265 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// The most sprites the PPU can draw on one scanline.
+pub const SPRITE_LIMIT: usize = 8;
+
+/*
+ *   One of the sprites found on a scanline during evaluation. Its four OAM
+ *   bytes are
+ *
+ *   0 - Y position of the top of the sprite, minus one
+ *   1 - Tile index
+ *   2 - Attributes: VHP- --PP (flip vertically, flip horizontally, behind
+ *       background, palette)
+ *   3 - X position of the left of the sprite
+ *
+ *   The row of the sprite on the line is worked out during evaluation,
+ *   flipped vertically if need be, as PPUCTRL may change the sprite size
+ *   before the pattern for that row is fetched at the end of the line. The
+ *   pattern comes already flipped horizontally.
+ */
+#[derive(Copy, Clone)]
+pub struct Sprite {
+    // Position in OAM, sprite zero being the first.
+    pub index: u8,
+    pub y: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    pub x: u8,
+    pub row: u8,
+    pub pattern_low: u8,
+    pub pattern_high: u8,
+}
+
+impl Sprite {
+    pub fn new() -> Self {
+        Sprite {
+            index: 0,
+            y: 0,
+            tile: 0,
+            attributes: 0,
+            x: 0,
+            row: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+        }
+    }
+
+    fn from_oam(oam: &[u8; 256], index: usize, scanline: u16, height: u16) -> Self {
+        let mut sprite = Sprite {
+            index: index as u8,
+            y: oam[index * 4],
+            tile: oam[index * 4 + 1],
+            attributes: oam[index * 4 + 2],
+            x: oam[index * 4 + 3],
+            row: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+        };
+        let row = scanline.wrapping_sub(sprite.y as u16) as u8;
+        sprite.row = match sprite.flip_vertical() {
+            true => height as u8 - 1 - row,
+            false => row,
+        };
+        sprite
+    }
+
+    pub fn palette(&self) -> u8 {
+        self.attributes & 0b11
+    }
+
+    pub fn behind_background(&self) -> bool {
+        self.attributes & 0b00100000 != 0
+    }
+
+    pub fn flip_horizontal(&self) -> bool {
+        self.attributes & 0b01000000 != 0
+    }
+
+    pub fn flip_vertical(&self) -> bool {
+        self.attributes & 0b10000000 != 0
+    }
+
+    // Colour 0 - 3 of the sprite at screen column x, 0 (transparent)
+    // outside of it.
+    pub fn pixel(&self, x: usize) -> u8 {
+        let offset = x.wrapping_sub(self.x as usize);
+        if offset >= 8 {
+            return 0;
+        }
+        let bit = 7 - offset;
+        ((self.pattern_high >> bit) & 0b1) << 1 | ((self.pattern_low >> bit) & 0b1)
+    }
+}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+ *   https://www.nesdev.org/wiki/PPU_sprite_evaluation
+ *
+ *   Find the sprites on the next scanline, in OAM order, and whether the
+ *   line overflowed. Once secondary OAM holds 8 sprites the PPU keeps
+ *   scanning for a ninth to set the overflow flag, but a bug increments the
+ *   byte offset within each sprite along with the sprite index. It ends up
+ *   comparing tile indices, attributes and X positions against the
+ *   scanline, giving both false positives and false negatives.
+ *
+ *   With the limit lifted every sprite on the line is kept, though the
+ *   overflow flag still comes out as it would on hardware.
+ */
+pub fn evaluate(
+    oam: &[u8; 256],
+    scanline: u16,
+    height: u16,
+    limit: bool,
+    sprites: &mut Vec<Sprite>,
+) -> bool {
+    let on_line = |y: u8| scanline.wrapping_sub(y as u16) < height;
+    sprites.clear();
+    for index in 0..64 {
+        if on_line(oam[index * 4]) && (sprites.len() < SPRITE_LIMIT || !limit) {
+            sprites.push(Sprite::from_oam(oam, index, scanline, height));
+        }
+    }
+
+    let mut found = 0;
+    let mut offset = 0;
+    for index in 0..64 {
+        if found < SPRITE_LIMIT {
+            if on_line(oam[index * 4]) {
+                found += 1;
+            }
+        } else if on_line(oam[index * 4 + offset]) {
+            return true;
+        } else {
+            offset = (offset + 1) % 4;
+        }
+    }
+    false
+}
+
+impl Snapshot for Sprite {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.index);
+        state.write_u8(self.y);
+        state.write_u8(self.tile);
+        state.write_u8(self.attributes);
+        state.write_u8(self.x);
+        state.write_u8(self.row);
+        state.write_u8(self.pattern_low);
+        state.write_u8(self.pattern_high);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.index = state.read_u8()?;
+        self.y = state.read_u8()?;
+        self.tile = state.read_u8()?;
+        self.attributes = state.read_u8()?;
+        self.x = state.read_u8()?;
+        self.row = state.read_u8()?;
+        self.pattern_low = state.read_u8()?;
+        self.pattern_high = state.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCANLINE: u16 = 20;
+    // Y position of a sprite covering SCANLINE, and of one that doesn't.
+    const ON_LINE: u8 = 16;
+    const OFF_LINE: u8 = 100;
+
+    // OAM with every sprite off the line, its other bytes too.
+    fn empty_oam() -> [u8; 256] {
+        [0xFF; 256]
+    }
+
+    fn evaluate_oam(oam: &[u8; 256], limit: bool) -> (bool, Vec<u8>) {
+        let mut sprites = Vec::new();
+        let overflow = evaluate(oam, SCANLINE, 8, limit, &mut sprites);
+        (overflow, sprites.iter().map(|sprite| sprite.index).collect())
+    }
+
+    #[test]
+    fn finds_sprites_on_the_line() {
+        let mut oam = empty_oam();
+        oam[3 * 4..3 * 4 + 4].copy_from_slice(&[ON_LINE, 0x42, 0b10000001, 200]);
+        // Sprite 5's bottom row is on the line, sprite 6 ends just above it.
+        oam[5 * 4..5 * 4 + 3].copy_from_slice(&[SCANLINE as u8 - 7, 0x43, 0]);
+        oam[6 * 4..6 * 4 + 3].copy_from_slice(&[SCANLINE as u8 - 8, 0x44, 0]);
+        let mut sprites = Vec::new();
+        assert!(!evaluate(&oam, SCANLINE, 8, true, &mut sprites));
+        assert_eq!(sprites.len(), 2);
+        assert_eq!(sprites[0].index, 3);
+        assert_eq!(sprites[0].tile, 0x42);
+        assert_eq!(sprites[0].palette(), 1);
+        assert!(sprites[0].flip_vertical());
+        assert_eq!(sprites[0].x, 200);
+        // Row 4 of the sprite, flipped.
+        assert_eq!(sprites[0].row, 3);
+        assert_eq!(sprites[1].index, 5);
+        assert_eq!(sprites[1].row, 7);
+
+        // 8x16 sprites cover twice as many lines.
+        assert!(!evaluate(&oam, SCANLINE, 16, true, &mut sprites));
+        assert_eq!(sprites.len(), 3);
+        assert_eq!(sprites[0].row, 11);
+        assert_eq!(sprites[2].row, 8);
+    }
+
+    #[test]
+    fn eight_sprites_dont_overflow() {
+        let mut oam = empty_oam();
+        for index in 0..8 {
+            oam[index * 8] = ON_LINE;
+        }
+        assert_eq!(evaluate_oam(&oam, true), (false, vec![0, 2, 4, 6, 8, 10, 12, 14]));
+    }
+
+    #[test]
+    fn ninth_sprite_overflows() {
+        let mut oam = empty_oam();
+        for index in 0..9 {
+            oam[index * 4] = ON_LINE;
+        }
+        assert_eq!(evaluate_oam(&oam, true), (true, (0..8).collect()));
+        // Without the limit every sprite is drawn, and the flag still set.
+        assert_eq!(evaluate_oam(&oam, false), (true, (0..9).collect()));
+    }
+
+    #[test]
+    fn overflow_misses_sprite_after_a_gap() {
+        let mut oam = empty_oam();
+        for index in 0..8 {
+            oam[index * 4] = ON_LINE;
+        }
+        // Sprite 8 is off the line, so sprite 9 is checked by its tile
+        // index rather than its Y position and the overflow is missed.
+        oam[8 * 4] = OFF_LINE;
+        oam[9 * 4] = ON_LINE;
+        assert_eq!(evaluate_oam(&oam, true), (false, (0..8).collect()));
+        assert_eq!(evaluate_oam(&oam, false), (false, vec![0, 1, 2, 3, 4, 5, 6, 7, 9]));
+    }
+
+    #[test]
+    fn overflow_from_a_sprite_off_the_line() {
+        let mut oam = empty_oam();
+        for index in 0..8 {
+            oam[index * 4] = ON_LINE;
+        }
+        // Sprites 8 - 10 are off the line, but the diagonal scan reads
+        // sprite 10's attributes as a Y position on it.
+        oam[8 * 4] = OFF_LINE;
+        oam[9 * 4] = OFF_LINE;
+        oam[10 * 4] = OFF_LINE;
+        oam[10 * 4 + 2] = ON_LINE;
+        assert_eq!(evaluate_oam(&oam, true), (true, (0..8).collect()));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+
+/*
+ *   Rewind buffer
+ *
+ *   Holds the most recent save state in full, plus a ring of deltas leading
+ *   back from it. Each delta is the XOR of two consecutive states, which is
+ *   almost entirely zero as little of the machine changes in a frame, and is
+ *   run-length encoded as a sequence of
+ *
+ *     zero run length (varint), literal length (varint), literal bytes
+ *
+ *   Stepping back XORs the newest delta into the current state, so the
+ *   oldest delta can be dropped without touching any of the others.
+ */
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    // `capacity` is the number of steps back that are kept.
+    pub fn new(capacity: usize) -> Self {
+        RewindBuffer {
+            capacity,
+            latest: Vec::new(),
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        // States from one cartridge are always the same size, anything else
+        // means the history no longer applies.
+        if self.latest.len() != state.len() {
+            self.deltas.clear();
+        } else {
+            self.deltas.push_back(encode_delta(&self.latest, &state));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = state;
+    }
+
+    // Step back to the state pushed before the latest one.
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        apply_delta(&delta, &mut self.latest);
+        Some(&self.latest)
+    }
+
+    pub fn clear(&mut self) {
+        self.latest.clear();
+        self.deltas.clear();
+    }
+
+    // Number of steps that can currently be rewound.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    // Memory used by the stored deltas and latest state, in bytes.
+    pub fn size(&self) -> usize {
+        self.latest.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+fn encode_delta(previous: &[u8], next: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut position = 0;
+    while position < next.len() {
+        let zeros = previous[position..]
+            .iter()
+            .zip(&next[position..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        position += zeros;
+        let literals = previous[position..]
+            .iter()
+            .zip(&next[position..])
+            .take_while(|(a, b)| a != b)
+            .count();
+        write_varint(&mut delta, zeros);
+        write_varint(&mut delta, literals);
+        delta.extend(
+            previous[position..position + literals]
+                .iter()
+                .zip(&next[position..position + literals])
+                .map(|(a, b)| a ^ b),
+        );
+        position += literals;
+    }
+    delta
+}
+
+fn apply_delta(delta: &[u8], state: &mut [u8]) {
+    let mut input = delta;
+    let mut position = 0;
+    while !input.is_empty() {
+        position += read_varint(&mut input);
+        let literals = read_varint(&mut input);
+        for (byte, xor) in state[position..position + literals].iter_mut().zip(input) {
+            *byte ^= xor;
+        }
+        input = &input[literals..];
+        position += literals;
+    }
+}
+
+// LEB128: seven bits per byte, with the top bit set on all but the last.
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = input.split_first() {
+        *input = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(previous: &[u8], next: &[u8]) {
+        let delta = encode_delta(previous, next);
+        let mut state = previous.to_vec();
+        apply_delta(&delta, &mut state);
+        assert_eq!(state, next);
+        // XOR works both ways, which is what lets pop step backwards.
+        apply_delta(&delta, &mut state);
+        assert_eq!(state, previous);
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        round_trip(&[], &[]);
+        round_trip(&[1, 2, 3], &[1, 2, 3]);
+        round_trip(&[1, 2, 3, 4], &[0, 2, 3, 5]);
+        round_trip(&[0; 300], &[0xFF; 300]);
+        let previous: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut next = previous.clone();
+        next[0] ^= 1;
+        next[500..700].fill(0xAA);
+        next[999] ^= 0x80;
+        round_trip(&previous, &next);
+    }
+
+    #[test]
+    fn unchanged_state_encodes_to_a_single_run() {
+        let state = vec![0x55; 10000];
+        let delta = encode_delta(&state, &state);
+        // 10000 as a varint, then a literal length of 0.
+        assert_eq!(delta, [0x90, 0x4E, 0x00]);
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, usize::MAX >> 1] {
+            let mut output = Vec::new();
+            write_varint(&mut output, value);
+            let mut input = output.as_slice();
+            assert_eq!(read_varint(&mut input), value);
+            assert!(input.is_empty());
+        }
+    }
+
+    #[test]
+    fn pops_states_in_reverse() {
+        let mut rewind = RewindBuffer::new(2);
+        for value in 0..4 {
+            rewind.push(vec![value; 16]);
+        }
+        // Only two steps back are kept.
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.pop(), Some(&[2; 16][..]));
+        assert_eq!(rewind.pop(), Some(&[1; 16][..]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn state_of_another_size_clears_history() {
+        let mut rewind = RewindBuffer::new(10);
+        rewind.push(vec![0; 16]);
+        rewind.push(vec![1; 16]);
+        rewind.push(vec![2; 32]);
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+
+/*
+ *   Save state format
+ *
+ *   A state starts with the magic bytes "NESS" and a little endian u16
+ *   format version, followed by each component of the machine in a fixed
+ *   order: CPU, bus, PPU, APU, joypad and finally the mapper. All multi-byte
+ *   values are little endian. Variable length data (RAM whose size depends
+ *   on the cartridge) is prefixed with its length as a u32.
+ *
+ *   STATE_VERSION must be bumped whenever the layout changes, since old
+ *   states can't be read back with a different layout.
+ */
+const STATE_MAGIC: [u8; 4] = *b"NESS";
+pub const STATE_VERSION: u16 = 6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    // The data doesn't start with the save state magic bytes.
+    BadMagic,
+    UnsupportedVersion(u16),
+    // The data ended before the whole machine was read.
+    UnexpectedEnd,
+    // The state was made with a different cartridge.
+    CartridgeMismatch,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "save state version {} is not supported", version)
+            }
+            StateError::UnexpectedEnd => write!(f, "save state is truncated"),
+            StateError::CartridgeMismatch => {
+                write!(f, "save state was made with a different cartridge")
+            }
+            StateError::Invalid(reason) => write!(f, "invalid save state: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+// Implemented by each part of the machine that holds state.
+pub trait Snapshot {
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.write_bytes(&STATE_MAGIC);
+        writer.write_u16(STATE_VERSION);
+        writer
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    // Fixed size data, whose length the reader already knows.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Data whose length depends on the cartridge.
+    pub fn write_vec(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.write_bytes(bytes);
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        let mut reader = StateReader { data, position: 0 };
+        let mut magic = [0; 4];
+        reader.read_bytes(&mut magic).map_err(|_| StateError::BadMagic)?;
+        if magic != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        match reader.read_u16()? {
+            STATE_VERSION => Ok(reader),
+            version => Err(StateError::UnsupportedVersion(version)),
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        let end = self.position.checked_add(length).ok_or(StateError::UnexpectedEnd)?;
+        let bytes = self.data.get(self.position..end).ok_or(StateError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, StateError> {
+        Ok(self.read_u64()? as usize)
+    }
+
+    pub fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), StateError> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    // Read length prefixed data into a buffer which must already be the
+    // right size, as RAM sizes are fixed by the cartridge.
+    pub fn read_vec(&mut self, bytes: &mut [u8]) -> Result<(), StateError> {
+        if self.read_u32()? as usize != bytes.len() {
+            return Err(StateError::CartridgeMismatch);
+        }
+        self.read_bytes(bytes)
+    }
+
+    // Whether everything has been read, as a check that the layouts agree.
+    pub fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+
+    // NROM with 16KB of PRG ROM that loops forever at 0x8000, writing
+    // incrementing values to RAM so consecutive states differ.
+    fn test_nes() -> Nes {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        rom.resize(16, 0);
+        let mut prg = vec![0xEA; 0x4000];
+        // INC $00; JMP $8000
+        prg[..5].copy_from_slice(&[0xE6, 0x00, 0x4C, 0x00, 0x80]);
+        // Reset vector
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+        Nes::from_rom(&rom).unwrap()
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u32(0x789ABCDE);
+        writer.write_u64(0x0123456789ABCDEF);
+        writer.write_usize(42);
+        writer.write_vec(&[1, 2, 3]);
+        let data = writer.finish();
+
+        let mut reader = StateReader::new(&data).unwrap();
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_u32(), Ok(0x789ABCDE));
+        assert_eq!(reader.read_u64(), Ok(0x0123456789ABCDEF));
+        assert_eq!(reader.read_usize(), Ok(42));
+        let mut bytes = [0; 3];
+        assert_eq!(reader.read_vec(&mut bytes), Ok(()));
+        assert_eq!(bytes, [1, 2, 3]);
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_u8(), Err(StateError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut data = STATE_MAGIC.to_vec();
+        data.extend_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            StateReader::new(&data).err(),
+            Some(StateError::UnsupportedVersion(STATE_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        assert_eq!(StateReader::new(b"NES").err(), Some(StateError::BadMagic));
+        assert_eq!(StateReader::new(b"SSEN\x04\x00").err(), Some(StateError::BadMagic));
+    }
+
+    #[test]
+    fn rejects_ram_of_the_wrong_size() {
+        let mut writer = StateWriter::new();
+        writer.write_vec(&[0; 4]);
+        let data = writer.finish();
+        let mut reader = StateReader::new(&data).unwrap();
+        let mut bytes = [0; 8];
+        assert_eq!(reader.read_vec(&mut bytes), Err(StateError::CartridgeMismatch));
+    }
+
+    #[test]
+    fn machine_round_trip() {
+        let mut nes = test_nes();
+        nes.step_frame();
+        let state = nes.save_state();
+        nes.step_frame();
+        assert_ne!(nes.save_state(), state);
+
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.save_state(), state);
+    }
+
+    #[test]
+    fn failed_load_leaves_machine_untouched() {
+        let mut nes = test_nes();
+        nes.step_frame();
+        let state = nes.save_state();
+        let truncated = &state[..state.len() / 2];
+        assert_eq!(nes.load_state(truncated), Err(StateError::UnexpectedEnd));
+        assert_eq!(nes.save_state(), state);
+    }
+}